@@ -0,0 +1,155 @@
+//! Entries in DeaDBeeF's menus and hotkey list.
+//!
+//! Actions are triggered from the GUI thread, and take the service lock
+//! like the message thread does before touching the service.
+use std::{
+    env,
+    ffi::{CStr, CString},
+    io::{self, Write},
+    os::raw::c_int,
+    process::{Command as Process, Stdio},
+    ptr,
+};
+
+use empress::{
+    deadbeef::{self, Command, Deadbeef},
+    error, info,
+    log::{self, Level},
+    uri::Location,
+    warn,
+};
+
+use crate::{dump_state, guarded, restart, API};
+
+/// Names and menu titles of the actions, in menu order.
+const ACTIONS: &[(&str, &str)] = &[
+    ("mpris_restart", "MPRIS: restart service"),
+    ("mpris_copy_uri", "MPRIS: copy current track URI"),
+    ("mpris_toggle_enable", "MPRIS: toggle enable"),
+    ("mpris_dump_state", "MPRIS: dump state to log"),
+];
+
+/// Head of the action list, built by [`build`].
+static mut HEAD: *mut deadbeef::DB_plugin_action_t = ptr::null_mut();
+
+/// Builds the action list. It lives as long as the plugin is loaded.
+pub unsafe fn build() {
+    let mut next = ptr::null_mut();
+    for (name, title) in ACTIONS.iter().rev() {
+        let mut action: deadbeef::DB_plugin_action_t = std::mem::zeroed();
+        action.name = CString::new(*name).unwrap().into_raw();
+        action.title = CString::new(*title).unwrap().into_raw();
+        action.flags = deadbeef::DB_ACTION_COMMON as _;
+        action.callback2 = Some(run);
+        action.next = next;
+        next = Box::into_raw(Box::new(action));
+    }
+    HEAD = next;
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn get_actions(
+    _it: *mut deadbeef::DB_playItem_t,
+) -> *mut deadbeef::DB_plugin_action_t {
+    guarded("get_actions", ptr::null_mut(), || HEAD)
+}
+
+unsafe extern "C" fn run(
+    action: *mut deadbeef::DB_plugin_action_t,
+    _ctx: deadbeef::ddb_action_context_t,
+) -> c_int {
+    guarded("action", -1, || {
+        let (name, db) = match (action.as_ref(), API) {
+            (Some(action), Some(db)) if !action.name.is_null() => (CStr::from_ptr(action.name), db),
+            _ => return -1,
+        };
+
+        match name.to_bytes() {
+            b"mpris_restart" => restart(db),
+            b"mpris_copy_uri" => copy_uri(db),
+            b"mpris_toggle_enable" => toggle_enable(db),
+            b"mpris_dump_state" => dump_state(),
+            _ => return -1,
+        }
+        0
+    })
+}
+
+fn toggle_enable(db: Deadbeef) {
+    let enabled = db.conf_int("ddb_mpris.checked", 1) != 0;
+    // The config-changed message registers or unregisters the service
+    let result = db
+        .conf_set_int("ddb_mpris.checked", (!enabled) as i32)
+        .and_then(|_| db.send(Command::ConfigChanged));
+    if let Err(e) = result {
+        error!("unable to toggle the MPRIS service: {}", e);
+    }
+}
+
+fn copy_uri(db: Deadbeef) {
+    let track = match db.playing_track() {
+        Some(track) => track,
+        None => {
+            info!("nothing is playing, no URI to copy");
+            return;
+        }
+    };
+    let uri = match db.metadata(&track) {
+        Ok(meta) => meta
+            .into_iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(":uri"))
+            .map(|(_, value)| Location::parse(&value).to_uri()),
+        Err(e) => {
+            error!("unable to read the playing track: {}", e);
+            return;
+        }
+    };
+
+    match uri {
+        Some(uri) => match copy_to_clipboard(&uri) {
+            Ok(()) => info!("copied {} to the clipboard", uri),
+            // Always shown, so the URI can still be copied from the log
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                log::write(Level::Info, format_args!("{}, current track: {}", e, uri))
+            }
+            Err(e) => warn!("unable to copy {} to the clipboard: {}", uri, e),
+        },
+        None => warn!("the playing track has no URI"),
+    }
+}
+
+/// Puts `text` on the clipboard with the first clipboard tool found.
+fn copy_to_clipboard(text: &str) -> io::Result<()> {
+    let mut tools: Vec<(&str, &[&str])> = vec![
+        ("xclip", &["-selection", "clipboard"]),
+        ("xsel", &["--clipboard", "--input"]),
+    ];
+    if env::var_os("WAYLAND_DISPLAY").is_some() {
+        tools.insert(0, ("wl-copy", &[]));
+    }
+
+    for (program, args) in tools {
+        let mut child = match Process::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+        {
+            Ok(child) => child,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(text.as_bytes())?;
+        }
+        // The tools fork to serve the selection, so this returns promptly
+        std::thread::spawn(move || child.wait());
+        return Ok(());
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::NotFound,
+        "none of wl-copy, xclip or xsel is installed",
+    ))
+}
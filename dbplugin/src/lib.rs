@@ -1,7 +1,25 @@
 #![deny(clippy::all)]
-use std::ffi::CString;
+mod actions;
 
-use empress::{deadbeef, mpris::MPRIS};
+use std::{
+    ffi::CString,
+    panic::{catch_unwind, AssertUnwindSafe},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex, MutexGuard,
+    },
+    thread::JoinHandle,
+};
+
+use empress::{
+    backend::DeadbeefBackend,
+    deadbeef::{self, ApiVersion, Deadbeef, DeadbeefEvent},
+    error, info,
+    log::{self, Level},
+    mpris::MPRIS,
+    settings::Settings,
+};
 
 const NO: i8 = 1;
 
@@ -35,7 +53,88 @@ static mut LIST_FILTER: deadbeef::DB_plugin_t = deadbeef::DB_plugin_t {
     stop: None,
 };
 
-static mut API: Option<&deadbeef::DB_functions_t> = None;
+static mut API: Option<Deadbeef> = None;
+
+/// Set once a panic has been caught, after which every entry point is a no-op.
+static DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Runs `f` for the entry point `name`, ensuring no panic unwinds across
+/// the C boundary into DeaDBeeF. A panic is logged, the plugin is disabled,
+/// and `fallback` is returned instead.
+fn guarded<T>(name: &str, fallback: T, f: impl FnOnce() -> T) -> T {
+    if DISABLED.load(Ordering::SeqCst) {
+        return fallback;
+    }
+
+    match catch_unwind(AssertUnwindSafe(f)) {
+        Ok(ret) => ret,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown panic");
+            error!("panic in {}: {}; disabling plugin", name, msg);
+            disable();
+            fallback
+        }
+    }
+}
+
+/// Disables the plugin, shutting down the MPRIS service if it is running.
+fn disable() {
+    DISABLED.store(true, Ordering::SeqCst);
+    let _ = catch_unwind(|| unsafe { EMPRESS.exit() });
+
+    // The listener cannot join itself, so after its own panics the service
+    // is released by `stop`
+    if std::thread::current().name() != Some(LISTENER_NAME) {
+        unsafe { release() };
+    }
+}
+
+/// Joins the listener and unregisters the service, each step on its own
+/// so a disabled plugin still gives up its bus name.
+unsafe fn release() {
+    let _service = lock_service();
+    let _ = catch_unwind(AssertUnwindSafe(|| {
+        if let Some(listener) = LISTENER.take() {
+            let _ = listener.join();
+        }
+    }));
+    let _ = catch_unwind(AssertUnwindSafe(|| EMPRESS.shutdown()));
+}
+
+/// Config dialog entries for the settings every build has.
+const DIALOG: &str = r#"property "Enable" checkbox ddb_mpris.checked 1;
+property "Bus name suffix (used when another player owns the name)" entry ddb_mpris.instance_suffix "";
+property "Title format (empty for the title tag)" entry ddb_mpris.tf_title "";
+property "Artist format (empty for the artist tag)" entry ddb_mpris.tf_artist "";
+property "Album format (empty for the album tag)" entry ddb_mpris.tf_album "";
+property "Rating tags (TAG=best value, separated by ;)" entry ddb_mpris.rating_tags "FMPS_RATING=1;RATING=5";
+property "Prevent sleep while playing" select[3] ddb_mpris.inhibit 0 Off logind ScreenSaver;
+property "Log level" select[6] ddb_mpris.log_level 0 Off Error Warning Info Debug Trace;
+"#;
+
+#[cfg(feature = "art")]
+const ART_DIALOG: &str = r#"property "Use the artwork plugin for album art" checkbox ddb_mpris.art_plugin 1;
+property "Album art file patterns (separated by ;)" entry ddb_mpris.art_patterns "folder.*;cover.*;front.*;AlbumArt*.jpg;Scans/front.*;Scans/cover.*;Artwork/front.*;Artwork/cover.*";
+property "Parent folders searched for album art" entry ddb_mpris.art_search_depth 1;
+property "Extract album art embedded in tags" checkbox ddb_mpris.art_embedded 1;
+property "Album art cache directory (empty for default)" entry ddb_mpris.art_cache_dir "";
+property "Album art cache size (MB)" entry ddb_mpris.art_cache_size_mb 64;
+"#;
+
+#[cfg(feature = "thumbnail")]
+const THUMBNAIL_DIALOG: &str = r#"property "Downscale large album art" checkbox ddb_mpris.art_thumbnail 0;
+property "Largest album art size (pixels)" entry ddb_mpris.art_thumbnail_size 512;
+"#;
+
+/// Config dialog entries for track change notifications.
+#[cfg(feature = "notifications")]
+const NOTIFY_DIALOG: &str = r#"property "Show a notification on track change" checkbox ddb_mpris.notify 0;
+property "Notification text format (empty for artist and album)" entry ddb_mpris.notify_format "";
+"#;
 
 #[no_mangle]
 // Note: the name here _must_ match the name of the final
@@ -44,11 +143,22 @@ static mut API: Option<&deadbeef::DB_functions_t> = None;
 unsafe extern "C" fn mpris_load(
     api: *const deadbeef::DB_functions_t,
 ) -> *const deadbeef::DB_plugin_t {
-    let dialog = CString::new(
-        r#"property "Enable" checkbox ddb_mpris.checked 0;
-"#,
-    )
-    .unwrap();
+    guarded("mpris_load", std::ptr::null(), || load(api))
+}
+
+unsafe fn load(api: *const deadbeef::DB_functions_t) -> *const deadbeef::DB_plugin_t {
+    #[cfg_attr(
+        not(any(feature = "art", feature = "notifications")),
+        allow(unused_mut)
+    )]
+    let mut dialog = String::from(DIALOG);
+    #[cfg(feature = "art")]
+    dialog.push_str(ART_DIALOG);
+    #[cfg(feature = "thumbnail")]
+    dialog.push_str(THUMBNAIL_DIALOG);
+    #[cfg(feature = "notifications")]
+    dialog.push_str(NOTIFY_DIALOG);
+    let dialog = CString::new(dialog).unwrap();
 
     let name = CString::new("MPRIS").unwrap();
     let id = CString::new("mpris").unwrap();
@@ -74,8 +184,9 @@ unsafe extern "C" fn mpris_load(
 
         configdialog: dialog.into_raw(),
 
-        api_vmajor: 1,
-        api_vminor: 15,
+        // Newer functions are only used when the host reports them
+        api_vmajor: ApiVersion::MIN.major as _,
+        api_vminor: ApiVersion::MIN.minor as _,
         flags: 0,
         reserved1: 0,
         reserved2: 0,
@@ -85,34 +196,162 @@ unsafe extern "C" fn mpris_load(
         connect: None,
         disconnect: None,
         exec_cmdline: None,
-        get_actions: None,
+        get_actions: Some(actions::get_actions),
         message: Some(handle_message),
         start: Some(start),
         stop: Some(stop),
     };
 
-    API = api.as_ref();
+    API = api.as_ref().map(Deadbeef::new);
+    log::set_sink(API);
+    actions::build();
 
     &LIST_FILTER
 }
 
 static mut EMPRESS: MPRIS = MPRIS::uninit();
 
+/// Thread serving D-Bus requests while the service is registered.
+static mut LISTENER: Option<JoinHandle<()>> = None;
+
+const LISTENER_NAME: &str = "mpris-listener";
+
+/// Held while the service is used or changed, which happens on the message
+/// thread and, through actions, on the GUI thread.
+static SERVICE: Mutex<()> = Mutex::new(());
+
+fn lock_service() -> MutexGuard<'static, ()> {
+    SERVICE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Registers the MPRIS service and starts serving it.
+unsafe fn register(db: Deadbeef, settings: &Settings) -> bool {
+    if let Err(e) = EMPRESS.init(
+        "org.mpris.MediaPlayer2.DeaDBeeF",
+        settings,
+        Rc::new(DeadbeefBackend::new(db)),
+    ) {
+        error!("unable to register MPRIS service: {}", e);
+        EMPRESS.shutdown();
+        return false;
+    }
+    let listener = std::thread::Builder::new()
+        .name(LISTENER_NAME.to_string())
+        .spawn(|| guarded("listen", (), || EMPRESS.listen()));
+    match listener {
+        Ok(listener) => {
+            LISTENER = Some(listener);
+            true
+        }
+        Err(e) => {
+            error!("unable to start the MPRIS listener: {}", e);
+            EMPRESS.shutdown();
+            false
+        }
+    }
+}
+
+/// Stops serving the MPRIS service and releases its bus name.
+unsafe fn unregister() {
+    EMPRESS.exit();
+    if let Some(listener) = LISTENER.take() {
+        let _ = listener.join();
+    }
+    EMPRESS.shutdown();
+}
+
+/// Registers, unregisters or updates the service to match the config,
+/// registering it again if `restart` is set.
+unsafe fn apply_config(db: Deadbeef, restart: bool) {
+    let settings = Settings::from_conf(&db);
+    log::set_level(settings.log_level);
+
+    match (EMPRESS.is_running(), settings.enabled) {
+        (false, true) => {
+            register(db, &settings);
+        }
+        (true, false) => unregister(),
+        (true, true) => {
+            if restart || !EMPRESS.reconfigure(&settings) {
+                unregister();
+                register(db, &settings);
+            }
+        }
+        (false, false) => {}
+    }
+}
+
 #[no_mangle]
 unsafe extern "C" fn start() -> i32 {
-    EMPRESS.init("org.mpris.MediaPlayer2.DeaDBeeF", API.unwrap());
-    std::thread::spawn(move || EMPRESS.listen());
-    0
+    guarded("start", -1, || {
+        let db = match API {
+            Some(db) => db,
+            None => {
+                error!("started before the DeaDBeeF API was loaded");
+                return -1;
+            }
+        };
+        let settings = Settings::from_conf(&db);
+        log::set_level(settings.log_level);
+        info!(
+            "running on DeaDBeeF {}, artwork plugin {}",
+            db.capabilities(),
+            if db.artwork().is_some() {
+                "found"
+            } else {
+                "not found"
+            }
+        );
+
+        let _service = lock_service();
+        if !settings.enabled || EMPRESS.is_running() {
+            return 0;
+        }
+        if register(db, &settings) {
+            0
+        } else {
+            -1
+        }
+    })
 }
 
 #[no_mangle]
 unsafe extern "C" fn stop() -> i32 {
-    EMPRESS.exit();
-    0
+    // A disabled plugin may still hold the service
+    if DISABLED.load(Ordering::SeqCst) {
+        release();
+        return 0;
+    }
+    guarded("stop", 0, || {
+        let _service = lock_service();
+        unregister();
+        0
+    })
 }
 
 #[no_mangle]
 unsafe extern "C" fn handle_message(id: u32, ctx: usize, p1: u32, p2: u32) -> i32 {
-    EMPRESS.handle_event(id, ctx, p1, p2);
-    0
+    guarded("handle_message", 0, || {
+        if let Some(db) = API.as_ref() {
+            let event = DeadbeefEvent::decode(db, id, ctx, p1, p2);
+            let _service = lock_service();
+            if event == DeadbeefEvent::ConfigChanged {
+                apply_config(*db, false);
+            }
+            EMPRESS.handle_event(event);
+        }
+        0
+    })
+}
+
+/// Registers the service again with the current config.
+unsafe fn restart(db: Deadbeef) {
+    let _service = lock_service();
+    apply_config(db, true);
+}
+
+/// Writes the service's state to the log, whatever the log level.
+unsafe fn dump_state() {
+    let _service = lock_service();
+    log::write(Level::Info, format_args!("state:\n{}", EMPRESS.describe()));
 }
@@ -1,22 +1,114 @@
 extern crate bindgen;
 
-use std::{env, path::PathBuf};
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
 
-const HEADER_PATH: &str = "deadbeef/include/deadbeef/deadbeef.h";
+/// Headers in the DeaDBeeF submodule.
+const SUBMODULE_INCLUDE_PATH: &str = "deadbeef/include";
+const SUBMODULE_ARTWORK_HEADER_PATH: &str = "deadbeef/plugins/artwork/artwork.h";
+
+/// Bindings checked in for the `pregenerated-bindings` feature.
+const PREGENERATED_PATH: &str = "bindings/deadbeef.rs";
 
 fn main() {
-    println!("cargo:rerun-if-changed={HEADER_PATH}");
+    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap()).join("deadbeef.rs");
+
+    if env::var_os("CARGO_FEATURE_PREGENERATED_BINDINGS").is_some() {
+        println!("cargo:rerun-if-env-changed=DEADBEEF_BINDINGS");
+        let source = env::var_os("DEADBEEF_BINDINGS")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(PREGENERATED_PATH));
+        println!("cargo:rerun-if-changed={}", source.display());
+
+        if let Err(e) = std::fs::copy(&source, &out_path) {
+            panic!(
+                "Unable to read pre-generated bindings from {}: {}. Generate them as \
+                 described in the README, or set DEADBEEF_BINDINGS",
+                source.display(),
+                e
+            );
+        }
+        return;
+    }
+
+    let include_path = include_path();
+    let header_path = include_path.join("deadbeef/deadbeef.h");
+    let artwork_header_path = artwork_header_path(&include_path);
+    println!("cargo:rerun-if-changed={}", header_path.display());
+    println!("cargo:rerun-if-changed={}", artwork_header_path.display());
 
     let bindings = bindgen::Builder::default()
-        .header(HEADER_PATH)
+        .header(header_path.to_string_lossy())
+        .header(artwork_header_path.to_string_lossy())
+        .clang_arg(format!("-I{}", include_path.display()))
         .parse_callbacks(Box::new(bindgen::CargoCallbacks::new()))
         .prepend_enum_name(false)
         .generate()
         .expect("Unable to generate DeaDBeeF bindings");
 
-    let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
-
     bindings
-        .write_to_file(out_path.join("deadbeef.rs"))
+        .write_to_file(out_path)
         .expect("Failed to write bindings");
 }
+
+/// Finds the directory containing `deadbeef/deadbeef.h`, from
+/// `DEADBEEF_INCLUDE_DIR`, the submodule, or pkg-config, in that order.
+fn include_path() -> PathBuf {
+    println!("cargo:rerun-if-env-changed=DEADBEEF_INCLUDE_DIR");
+    if let Some(dir) = env::var_os("DEADBEEF_INCLUDE_DIR") {
+        return PathBuf::from(dir);
+    }
+
+    let submodule = PathBuf::from(SUBMODULE_INCLUDE_PATH);
+    if submodule.join("deadbeef/deadbeef.h").is_file() {
+        return submodule;
+    }
+
+    let system = pkg_config::Config::new()
+        .cargo_metadata(false)
+        .probe("deadbeef")
+        .ok()
+        .and_then(|lib| {
+            lib.include_paths
+                .into_iter()
+                .find(|dir| dir.join("deadbeef/deadbeef.h").is_file())
+        });
+    match system {
+        Some(dir) => dir,
+        None => panic!(
+            "Unable to find the DeaDBeeF headers. Check out the submodule, install \
+             DeaDBeeF's development package, set DEADBEEF_INCLUDE_DIR, or enable the \
+             pregenerated-bindings feature"
+        ),
+    }
+}
+
+/// Finds the artwork plugin's header, from `DEADBEEF_ARTWORK_HEADER`, next
+/// to `deadbeef.h`, where DeaDBeeF installs it, or in the submodule when the
+/// headers are the submodule's, in that order.
+fn artwork_header_path(include_path: &Path) -> PathBuf {
+    println!("cargo:rerun-if-env-changed=DEADBEEF_ARTWORK_HEADER");
+    if let Some(path) = env::var_os("DEADBEEF_ARTWORK_HEADER") {
+        return PathBuf::from(path);
+    }
+
+    let installed = include_path.join("deadbeef/artwork.h");
+    if installed.is_file() {
+        return installed;
+    }
+
+    // Pairing other headers with the submodule's would mix API versions
+    let submodule = PathBuf::from(SUBMODULE_ARTWORK_HEADER_PATH);
+    if include_path == Path::new(SUBMODULE_INCLUDE_PATH) && submodule.is_file() {
+        return submodule;
+    }
+
+    panic!(
+        "Unable to find the artwork plugin's header next to {}. Set \
+         DEADBEEF_INCLUDE_DIR to headers that include deadbeef/artwork.h, or \
+         DEADBEEF_ARTWORK_HEADER to the header itself",
+        installed.display()
+    )
+}
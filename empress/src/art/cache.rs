@@ -0,0 +1,124 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::warn;
+
+/// Content-addressed store for extracted album art.
+///
+/// Each picture is written once, named after a hash of its bytes, and the
+/// least recently used files are removed once the cache outgrows its limit.
+pub(crate) struct ArtCache {
+    dir: PathBuf,
+    max_bytes: u64,
+}
+
+impl ArtCache {
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        Self { dir, max_bytes }
+    }
+
+    /// `$XDG_CACHE_HOME/deadbeef-mpris/art`, falling back to `~/.cache`.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .filter(|p| p.is_absolute())
+            .or_else(|| std::env::var_os("HOME").map(|h| PathBuf::from(h).join(".cache")))?;
+
+        Some(base.join("deadbeef-mpris").join("art"))
+    }
+
+    /// Stores `data` under `key` with the extension `ext`, returning its
+    /// path. Existing entries are reused rather than rewritten.
+    pub fn store_as(&self, key: &str, ext: &str, data: &[u8]) -> io::Result<PathBuf> {
+        let path = self.dir.join(format!("{}.{}", key, ext));
+        if self.touch(&path) {
+            return Ok(path);
+        }
+
+        fs::create_dir_all(&self.dir)?;
+
+        // Write to a temporary file first, so readers never see a partial image
+        let tmp = self
+            .dir
+            .join(format!(".{}.{}.tmp", key, std::process::id()));
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+
+        if let Err(e) = self.trim(&path) {
+            warn!("unable to trim album art cache: {}", e);
+        }
+
+        Ok(path)
+    }
+
+    /// Returns the entry stored under `key` with any of the extensions
+    /// `exts`, marking it as recently used.
+    #[cfg(feature = "thumbnail")]
+    pub fn get(&self, key: &str, exts: &[&str]) -> Option<PathBuf> {
+        exts.iter()
+            .map(|ext| self.dir.join(format!("{}.{}", key, ext)))
+            .find(|path| self.touch(path))
+    }
+
+    /// Stores `data` under a hash of its contents.
+    pub fn store(&self, ext: &str, data: &[u8]) -> io::Result<PathBuf> {
+        self.store_as(&format!("{:016x}", fnv1a(data)), ext, data)
+    }
+
+    /// Marks an existing entry as recently used.
+    fn touch(&self, path: &Path) -> bool {
+        match fs::File::options().append(true).open(path) {
+            Ok(f) => {
+                let _ = f.set_modified(SystemTime::now());
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Removes the least recently used entries, other than `keep`, until the
+    /// cache fits within its limit.
+    fn trim(&self, keep: &Path) -> io::Result<()> {
+        let mut entries = Vec::new();
+        let mut total = 0;
+
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let meta = entry.metadata()?;
+            if !meta.is_file() {
+                continue;
+            }
+            total += meta.len();
+            entries.push((
+                meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                meta.len(),
+                entry.path(),
+            ));
+        }
+
+        entries.sort();
+
+        for (_, len, path) in entries {
+            if total <= self.max_bytes {
+                break;
+            }
+            if path == keep {
+                continue;
+            }
+            fs::remove_file(&path)?;
+            total -= len;
+        }
+
+        Ok(())
+    }
+}
+
+/// 64-bit FNV-1a hash, stable across builds unlike `DefaultHasher`.
+pub(crate) fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
@@ -0,0 +1,673 @@
+//! Extraction of pictures embedded in audio file tags.
+//!
+//! Supports ID3v2 `APIC`/`PIC` frames, FLAC `PICTURE` blocks, MP4 `covr`
+//! atoms, and Vorbis comment `METADATA_BLOCK_PICTURE` fields in FLAC and
+//! Ogg files.
+use std::{
+    fs::File,
+    io::{self, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Pictures larger than this are ignored rather than loaded into memory.
+const MAX_PICTURE: u64 = 32 * 1024 * 1024;
+
+/// ID3v2 and FLAC picture type for the front cover.
+const FRONT_COVER: u32 = 3;
+
+/// An embedded image and its file extension.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Picture {
+    pub ext: &'static str,
+    pub data: Vec<u8>,
+}
+
+impl Picture {
+    /// Builds a picture from raw bytes, identifying the format from its
+    /// signature rather than the tag's MIME type, which is often wrong.
+    fn new(data: Vec<u8>) -> Option<Self> {
+        let ext = image_ext(&data)?;
+        Some(Self { ext, data })
+    }
+}
+
+/// Returns the extension for the image format of `data`, if recognised.
+pub(crate) fn image_ext(data: &[u8]) -> Option<&'static str> {
+    match data {
+        [0xff, 0xd8, 0xff, ..] => Some("jpg"),
+        [0x89, b'P', b'N', b'G', ..] => Some("png"),
+        [b'G', b'I', b'F', b'8', ..] => Some("gif"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("webp"),
+        [b'B', b'M', ..] => Some("bmp"),
+        _ => None,
+    }
+}
+
+/// Returns the front cover embedded in `path`, or the first picture if no
+/// front cover is marked.
+pub(crate) fn extract(path: &Path) -> io::Result<Option<Picture>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 12];
+    let read = read_up_to(&mut file, &mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    let pictures = match &magic[..read] {
+        [b'I', b'D', b'3', ..] => {
+            let pictures = id3v2(&mut file)?;
+            if pictures.is_empty() {
+                // FLAC files sometimes carry an ID3v2 tag before the stream
+                let mut marker = [0u8; 4];
+                match file.read_exact(&mut marker) {
+                    Ok(()) if &marker == b"fLaC" => flac(&mut file)?,
+                    _ => pictures,
+                }
+            } else {
+                pictures
+            }
+        }
+        [b'f', b'L', b'a', b'C', ..] => {
+            file.seek(SeekFrom::Start(4))?;
+            flac(&mut file)?
+        }
+        [b'O', b'g', b'g', b'S', ..] => ogg(&mut file)?,
+        [_, _, _, _, b'f', b't', b'y', b'p', ..] => mp4(&mut file)?,
+        _ => Vec::new(),
+    };
+
+    Ok(choose(pictures))
+}
+
+fn choose(mut pictures: Vec<(u32, Vec<u8>)>) -> Option<Picture> {
+    let idx = pictures
+        .iter()
+        .position(|(kind, _)| *kind == FRONT_COVER)
+        .unwrap_or(0);
+
+    if idx >= pictures.len() {
+        return None;
+    }
+    Picture::new(pictures.swap_remove(idx).1)
+}
+
+fn read_up_to(r: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut total = 0;
+    while total < buf.len() {
+        match r.read(&mut buf[total..])? {
+            0 => break,
+            n => total += n,
+        }
+    }
+    Ok(total)
+}
+
+fn read_vec(r: &mut impl Read, len: u64) -> io::Result<Vec<u8>> {
+    if len > MAX_PICTURE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "tag is too large",
+        ));
+    }
+    let mut buf = vec![0u8; len as usize];
+    r.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+fn be_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes([b[0], b[1], b[2], b[3]])
+}
+
+fn syncsafe(b: &[u8]) -> u32 {
+    b.iter()
+        .take(4)
+        .fold(0, |acc, v| (acc << 7) | (*v as u32 & 0x7f))
+}
+
+/// Reads the ID3v2 tag at the current position, leaving the reader just
+/// past it.
+fn id3v2(file: &mut File) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)?;
+
+    let version = header[3];
+    let flags = header[5];
+    let mut tag = read_vec(file, syncsafe(&header[6..10]) as u64)?;
+
+    if flags & 0x80 != 0 {
+        tag = unsynchronise(&tag);
+    }
+
+    let mut pos = 0;
+    if flags & 0x40 != 0 && tag.len() >= 4 {
+        pos = match version {
+            3 => be_u32(&tag) as usize + 4,
+            _ => syncsafe(&tag) as usize,
+        };
+    }
+
+    let (id_len, header_len) = if version == 2 { (3, 6) } else { (4, 10) };
+    let mut pictures = Vec::new();
+
+    while pos + header_len <= tag.len() {
+        let frame = &tag[pos..];
+        if frame[0] == 0 {
+            // Padding
+            break;
+        }
+
+        let id = &frame[..id_len];
+        let size = match version {
+            2 => u32::from_be_bytes([0, frame[3], frame[4], frame[5]]),
+            3 => be_u32(&frame[4..8]),
+            _ => syncsafe(&frame[4..8]),
+        } as usize;
+
+        // The size comes from the file, so it may point anywhere
+        let frame_len = match header_len.checked_add(size) {
+            Some(len) => len,
+            None => break,
+        };
+        let body = &frame[header_len..frame_len.min(frame.len())];
+
+        match id {
+            b"APIC" => pictures.extend(apic(body, false)),
+            b"PIC" => pictures.extend(apic(body, true)),
+            _ => {}
+        }
+
+        pos = match pos.checked_add(frame_len) {
+            Some(next) if next <= tag.len() => next,
+            _ => break,
+        };
+    }
+
+    Ok(pictures)
+}
+
+/// Reverses ID3v2 unsynchronisation, which inserts a zero after each `0xFF`.
+fn unsynchronise(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len());
+    let mut prev = 0;
+    for &b in data {
+        if !(prev == 0xff && b == 0) {
+            out.push(b);
+        }
+        prev = b;
+    }
+    out
+}
+
+/// Parses an `APIC` frame body, or a `PIC` frame body from ID3v2.2.
+fn apic(body: &[u8], v22: bool) -> Option<(u32, Vec<u8>)> {
+    let (&encoding, rest) = body.split_first()?;
+
+    let rest = if v22 {
+        // Three character image format
+        rest.get(3..)?
+    } else {
+        let mime_end = rest.iter().position(|b| *b == 0)?;
+        &rest[mime_end + 1..]
+    };
+
+    let (&kind, rest) = rest.split_first()?;
+
+    // The description is terminated by one zero byte, or two for UTF-16
+    let desc_len = if encoding == 1 || encoding == 2 {
+        rest.chunks(2).position(|c| c == [0, 0])? * 2 + 2
+    } else {
+        rest.iter().position(|b| *b == 0)? + 1
+    };
+
+    Some((kind as u32, rest.get(desc_len..)?.to_vec()))
+}
+
+/// Reads FLAC metadata blocks, starting just after the `fLaC` marker.
+fn flac(file: &mut File) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut pictures = Vec::new();
+
+    loop {
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header)?;
+
+        let last = header[0] & 0x80 != 0;
+        let kind = header[0] & 0x7f;
+        let len = u32::from_be_bytes([0, header[1], header[2], header[3]]) as u64;
+
+        match kind {
+            // PICTURE
+            6 => pictures.extend(flac_picture(&read_vec(file, len)?)),
+            // VORBIS_COMMENT
+            4 => pictures.extend(vorbis_comments(&read_vec(file, len)?)),
+            _ => {
+                file.seek(SeekFrom::Current(len as i64))?;
+            }
+        }
+
+        if last {
+            return Ok(pictures);
+        }
+    }
+}
+
+/// Parses a FLAC `PICTURE` block, as also used by `METADATA_BLOCK_PICTURE`.
+fn flac_picture(block: &[u8]) -> Option<(u32, Vec<u8>)> {
+    let field = |pos: usize| block.get(pos..pos + 4).map(be_u32);
+
+    let kind = field(0)?;
+    let mime_len = field(4)? as usize;
+    let desc_pos = 8 + mime_len;
+    let desc_len = field(desc_pos)? as usize;
+    // Width, height, depth and colour count follow the description
+    let data_len_pos = desc_pos + 4 + desc_len + 16;
+    let data_len = field(data_len_pos)? as usize;
+    let data = block.get(data_len_pos + 4..data_len_pos + 4 + data_len)?;
+
+    Some((kind, data.to_vec()))
+}
+
+/// Finds `METADATA_BLOCK_PICTURE` fields in a Vorbis comment block.
+fn vorbis_comments(block: &[u8]) -> Vec<(u32, Vec<u8>)> {
+    let le = |pos: usize| {
+        block
+            .get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    };
+
+    let mut pictures = Vec::new();
+    let vendor_len = match le(0) {
+        Some(l) => l,
+        None => return pictures,
+    };
+    let mut pos = 4 + vendor_len;
+    let count = le(pos).unwrap_or(0);
+    pos += 4;
+
+    for _ in 0..count {
+        let len = match le(pos) {
+            Some(l) => l,
+            None => break,
+        };
+        let comment = match block.get(pos + 4..pos + 4 + len) {
+            Some(c) => c,
+            None => break,
+        };
+        pos += 4 + len;
+
+        let (key, value) = match comment.iter().position(|b| *b == b'=') {
+            Some(eq) => (&comment[..eq], &comment[eq + 1..]),
+            None => continue,
+        };
+        if key.eq_ignore_ascii_case(b"METADATA_BLOCK_PICTURE") {
+            if let Some(picture) = base64(value).as_deref().and_then(flac_picture) {
+                pictures.push(picture);
+            }
+        }
+    }
+
+    pictures
+}
+
+fn base64(input: &[u8]) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+
+    let mut out = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc = 0u32;
+    let mut bits = 0;
+
+    for &c in input.iter().filter(|c| !c.is_ascii_whitespace()) {
+        if c == b'=' {
+            break;
+        }
+        acc = (acc << 6) | value(c)? as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((acc >> bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Reassembles the second packet of an Ogg stream, the comment header, and
+/// reads its pictures.
+fn ogg(file: &mut File) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let mut packets: Vec<Vec<u8>> = vec![Vec::new()];
+
+    while packets.len() < 3 {
+        let mut header = [0u8; 27];
+        if read_up_to(file, &mut header)? < header.len() || &header[..4] != b"OggS" {
+            break;
+        }
+
+        let mut lacing = vec![0u8; header[26] as usize];
+        file.read_exact(&mut lacing)?;
+
+        for len in lacing {
+            let current = packets.last_mut().unwrap();
+            let start = current.len();
+            current.resize(start + len as usize, 0);
+            file.read_exact(&mut current[start..])?;
+
+            if current.len() as u64 > MAX_PICTURE {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "tag is too large",
+                ));
+            }
+            // A lacing value below 255 ends the packet
+            if len < 255 {
+                packets.push(Vec::new());
+            }
+        }
+    }
+
+    let comments = match packets.get(1) {
+        Some(p) => p,
+        None => return Ok(Vec::new()),
+    };
+
+    let body = if let Some(rest) = comments.strip_prefix(b"\x03vorbis") {
+        rest
+    } else if let Some(rest) = comments.strip_prefix(b"OpusTags") {
+        rest
+    } else {
+        return Ok(Vec::new());
+    };
+
+    Ok(vorbis_comments(body))
+}
+
+/// Walks the MP4 box tree to `moov/udta/meta/ilst/covr`.
+fn mp4(file: &mut File) -> io::Result<Vec<(u32, Vec<u8>)>> {
+    let end = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(0))?;
+
+    // Only `moov` is loaded into memory, the media data is skipped
+    let moov = loop {
+        let pos = file.stream_position()?;
+        if pos + 8 > end {
+            return Ok(Vec::new());
+        }
+
+        let (kind, header_len, size) = mp4_box_header(file, end - pos)?;
+        if &kind == b"moov" {
+            break read_vec(file, size - header_len)?;
+        }
+        let next = pos
+            .checked_add(size)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid MP4 box"))?;
+        file.seek(SeekFrom::Start(next))?;
+    };
+
+    let mut pictures = Vec::new();
+    let covr = ["udta", "meta", "ilst", "covr"]
+        .iter()
+        .try_fold(&moov[..], |data, name| mp4_child(data, name.as_bytes()));
+
+    if let Some(covr) = covr {
+        let mut pos = 0;
+        while let Some((kind, body, next)) = mp4_box(covr, pos) {
+            // Each image is a `data` box: type, locale, then the image
+            if &kind == b"data" && body.len() > 8 {
+                pictures.push((FRONT_COVER, body[8..].to_vec()));
+            }
+            pos = next;
+        }
+    }
+
+    Ok(pictures)
+}
+
+/// Reads a box header from `file`, returning the type, header length and
+/// total size.
+fn mp4_box_header(file: &mut File, remaining: u64) -> io::Result<([u8; 4], u64, u64)> {
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+
+    let kind = [header[4], header[5], header[6], header[7]];
+    let (header_len, size) = match be_u32(&header) as u64 {
+        0 => (8, remaining),
+        1 => {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large)?;
+            (16, u64::from_be_bytes(large))
+        }
+        size => (8, size),
+    };
+
+    if size < header_len {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid MP4 box",
+        ));
+    }
+    Ok((kind, header_len, size))
+}
+
+/// Parses the box at `pos` in `data`, returning its type, body and the
+/// position of the next box.
+fn mp4_box(data: &[u8], pos: usize) -> Option<([u8; 4], &[u8], usize)> {
+    let header = data.get(pos..pos + 8)?;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (header_len, size) = match be_u32(header) as usize {
+        0 => (8, data.len() - pos),
+        1 => {
+            let large = data.get(pos + 8..pos + 16)?;
+            (16, u64::from_be_bytes(large.try_into().ok()?) as usize)
+        }
+        size => (8, size),
+    };
+
+    let body = data.get(pos + header_len..pos.checked_add(size)?)?;
+    Some((kind, body, pos + size.max(header_len)))
+}
+
+fn mp4_child<'a>(data: &'a [u8], name: &[u8]) -> Option<&'a [u8]> {
+    let mut pos = 0;
+    while let Some((kind, body, next)) = mp4_box(data, pos) {
+        if kind[..] == *name {
+            // `meta` is a full box, with a version and flags before its children
+            return if name == b"meta" {
+                body.get(4..)
+            } else {
+                Some(body)
+            };
+        }
+        pos = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 1, 2, 3];
+    const JPEG: &[u8] = &[0xff, 0xd8, 0xff, 0xe0, 4, 5, 6];
+
+    fn write(name: &str, data: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("empress-{}-{}", std::process::id(), name));
+        std::fs::write(&path, data).unwrap();
+        path
+    }
+
+    fn be(n: usize) -> [u8; 4] {
+        (n as u32).to_be_bytes()
+    }
+
+    fn le(n: usize) -> [u8; 4] {
+        (n as u32).to_le_bytes()
+    }
+
+    fn flac_picture_block(kind: u32, data: &[u8]) -> Vec<u8> {
+        let mime = b"image/png";
+        [
+            &kind.to_be_bytes()[..],
+            &be(mime.len()),
+            mime,
+            &be(0),
+            &[0u8; 16],
+            &be(data.len()),
+            data,
+        ]
+        .concat()
+    }
+
+    fn encode_base64(data: &[u8]) -> String {
+        const CHARS: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+        data.chunks(3)
+            .flat_map(|c| {
+                let n = (c[0] as u32) << 16
+                    | (*c.get(1).unwrap_or(&0) as u32) << 8
+                    | *c.get(2).unwrap_or(&0) as u32;
+                (0..4).map(move |i| {
+                    if i > c.len() {
+                        '='
+                    } else {
+                        CHARS[(n >> (18 - 6 * i)) as usize & 0x3f] as char
+                    }
+                })
+            })
+            .collect()
+    }
+
+    fn mp4_box(name: &[u8], body: &[u8]) -> Vec<u8> {
+        [&be(body.len() + 8)[..], name, body].concat()
+    }
+
+    fn ogg_page(packet: &[u8]) -> Vec<u8> {
+        let mut lacing = vec![255u8; packet.len() / 255];
+        lacing.push((packet.len() % 255) as u8);
+
+        let mut page = b"OggS".to_vec();
+        page.extend([0u8; 22]);
+        page.push(lacing.len() as u8);
+        page.extend(lacing);
+        page.extend(packet);
+        page
+    }
+
+    #[test]
+    fn id3v2_front_cover() {
+        let apic = |kind: u8, data: &[u8]| {
+            let body = [&[0u8][..], b"image/jpeg\0", &[kind], b"cover\0", data].concat();
+            [&b"APIC"[..], &be(body.len()), &[0u8, 0], &body].concat()
+        };
+        let frames = [apic(0, JPEG), apic(3, PNG)].concat();
+        let size = frames.len();
+        let header = [
+            b'I',
+            b'D',
+            b'3',
+            3,
+            0,
+            0,
+            (size >> 21) as u8 & 0x7f,
+            (size >> 14) as u8 & 0x7f,
+            (size >> 7) as u8 & 0x7f,
+            size as u8 & 0x7f,
+        ];
+
+        let path = write("id3.mp3", &[&header[..], &frames, &[0xff, 0xfb]].concat());
+        let picture = extract(&path).unwrap().unwrap();
+
+        assert_eq!(picture.ext, "png");
+        assert_eq!(picture.data, PNG);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn id3v2_oversized_frame() {
+        let frame = [&b"APIC"[..], &[0xff; 4], &[0u8, 0], JPEG].concat();
+        let size = frame.len();
+        let header = [b'I', b'D', b'3', 3, 0, 0, 0, 0, 0, size as u8];
+
+        let path = write("huge.mp3", &[&header[..], &frame].concat());
+        assert_eq!(extract(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn flac_metadata_block() {
+        let block = flac_picture_block(3, PNG);
+        let file = [&b"fLaC"[..], &[0x80u8 | 6], &be(block.len())[1..], &block].concat();
+
+        let path = write("picture.flac", &file);
+        let picture = extract(&path).unwrap().unwrap();
+        assert_eq!(picture.data, PNG);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mp4_covr_atom() {
+        let data = mp4_box(b"data", &[&be(13)[..], &be(0), JPEG].concat());
+        let ilst = mp4_box(b"ilst", &mp4_box(b"covr", &data));
+        let meta = mp4_box(b"meta", &[&[0u8; 4][..], &ilst].concat());
+        let moov = mp4_box(b"moov", &mp4_box(b"udta", &meta));
+        let file = [
+            mp4_box(b"ftyp", b"M4A \0\0\0\0"),
+            mp4_box(b"mdat", &[0; 64]),
+            moov,
+        ]
+        .concat();
+
+        let path = write("covr.m4a", &file);
+        let picture = extract(&path).unwrap().unwrap();
+        assert_eq!(picture.ext, "jpg");
+        assert_eq!(picture.data, JPEG);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn mp4_oversized_box() {
+        let huge = [&be(1)[..], b"free", &u64::MAX.to_be_bytes()].concat();
+        let file = [mp4_box(b"ftyp", b"M4A \0\0\0\0"), huge].concat();
+
+        let path = write("huge.m4a", &file);
+        let err = extract(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ogg_metadata_block_picture() {
+        let comment = format!(
+            "METADATA_BLOCK_PICTURE={}",
+            encode_base64(&flac_picture_block(3, PNG))
+        );
+        let tags = [
+            &b"OpusTags"[..],
+            &le(4),
+            b"test",
+            &le(1),
+            &le(comment.len()),
+            comment.as_bytes(),
+        ]
+        .concat();
+        let file = [
+            ogg_page(b"OpusHead\x01\x02\0\0\0\0\0\0\0\0\0"),
+            ogg_page(&tags),
+        ]
+        .concat();
+
+        let path = write("picture.opus", &file);
+        let picture = extract(&path).unwrap().unwrap();
+        assert_eq!(picture.data, PNG);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn no_picture() {
+        let path = write("plain.wav", b"RIFF\0\0\0\0WAVEfmt ");
+        assert_eq!(extract(&path).unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,277 @@
+//! Cover images stored as files next to the music.
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use glob::{MatchOptions, Pattern};
+
+use crate::warn;
+
+/// Extensions of the image formats players can be expected to display.
+const IMAGE_EXTENSIONS: &[&str] = &["jpg", "jpeg", "png", "webp", "gif", "bmp"];
+
+/// Directories remembered before the cache is emptied.
+const MAX_CACHED_DIRS: usize = 256;
+
+const OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: true,
+};
+
+/// Directories read during one search, with their mtimes before reading.
+type Listings = HashMap<PathBuf, (Option<SystemTime>, Vec<OsString>)>;
+
+/// Directories a search read, with their mtimes.
+type Visited = Vec<(PathBuf, Option<SystemTime>)>;
+
+/// Searches a track's directory, and optionally its parents, for a cover
+/// image matching an ordered list of glob patterns.
+pub(crate) struct FolderSearch {
+    /// Each pattern, split into one pattern per path component
+    patterns: Vec<Vec<Pattern>>,
+    depth: u32,
+    /// Result per directory, valid while the mtimes of every directory the
+    /// search read, including subfolders such as `Scans`, are unchanged
+    cache: RefCell<HashMap<PathBuf, (Visited, Option<PathBuf>)>>,
+}
+
+impl FolderSearch {
+    /// `patterns` are relative to the searched directory, so they may
+    /// reach into subfolders such as `Scans/front.*`. `depth` is the number
+    /// of parent directories searched when the track's own has no match,
+    /// for albums split into disc folders.
+    pub fn new(patterns: &[String], depth: u32) -> Self {
+        let patterns = patterns
+            .iter()
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| {
+                match p
+                    .split('/')
+                    .filter(|c| !c.is_empty())
+                    .map(Pattern::new)
+                    .collect::<Result<Vec<_>, _>>()
+                {
+                    Ok(components) => Some(components),
+                    Err(e) => {
+                        warn!("ignoring invalid cover pattern {:?}: {}", p, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+
+        Self {
+            patterns,
+            depth,
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the first image matching the patterns, searching the
+    /// directory of `track_path` before its parents.
+    pub fn find(&self, track_path: &Path) -> Option<PathBuf> {
+        track_path
+            .ancestors()
+            .skip(1)
+            .take(self.depth as usize + 1)
+            .find_map(|dir| self.find_in(dir))
+    }
+
+    fn find_in(&self, dir: &Path) -> Option<PathBuf> {
+        if let Some((visited, found)) = self.cache.borrow().get(dir) {
+            if visited.iter().all(|(dir, at)| modified(dir) == *at) {
+                return found.clone();
+            }
+        }
+
+        let mut listings = Listings::new();
+        let found = self
+            .patterns
+            .iter()
+            .find_map(|pattern| search(dir, pattern, &mut listings));
+        let visited = listings
+            .into_iter()
+            .map(|(dir, (at, _))| (dir, at))
+            .collect();
+
+        let mut cache = self.cache.borrow_mut();
+        if cache.len() >= MAX_CACHED_DIRS {
+            cache.clear();
+        }
+        cache.insert(dir.to_path_buf(), (visited, found.clone()));
+
+        found
+    }
+}
+
+/// Returns the first image under `dir` whose path components match
+/// `pattern`. Entries are matched by name, so `dir` itself need not be
+/// valid UTF-8.
+fn search(dir: &Path, pattern: &[Pattern], listings: &mut Listings) -> Option<PathBuf> {
+    let (component, rest) = pattern.split_first()?;
+    let literal = component.as_str();
+    let names = if literal == "." || literal == ".." {
+        vec![OsString::from(literal)]
+    } else {
+        list(dir, listings)
+            .into_iter()
+            .filter(|name| {
+                name.to_str()
+                    .map_or(false, |name| component.matches_with(name, OPTIONS))
+            })
+            .collect()
+    };
+
+    names.into_iter().find_map(|name| {
+        let path = dir.join(name);
+        if rest.is_empty() {
+            Some(path).filter(|p| is_image(p))
+        } else if path.is_dir() {
+            search(&path, rest, listings)
+        } else {
+            None
+        }
+    })
+}
+
+/// The sorted entry names of `dir`, read once per search.
+fn list(dir: &Path, listings: &mut Listings) -> Vec<OsString> {
+    listings
+        .entry(dir.to_path_buf())
+        .or_insert_with(|| {
+            let at = modified(dir);
+            let mut names: Vec<_> = std::fs::read_dir(dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|e| e.ok())
+                        .map(|e| e.file_name())
+                        .collect()
+                })
+                .unwrap_or_default();
+            names.sort();
+            (at, names)
+        })
+        .1
+        .clone()
+}
+
+fn modified(dir: &Path) -> Option<SystemTime> {
+    std::fs::metadata(dir).and_then(|m| m.modified()).ok()
+}
+
+fn is_image(path: &Path) -> bool {
+    let ext = match path.extension().and_then(|e| e.to_str()) {
+        Some(e) => e.to_ascii_lowercase(),
+        None => return false,
+    };
+    IMAGE_EXTENSIONS.contains(&ext.as_str()) && path.is_file()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    fn patterns(list: &[&str]) -> Vec<String> {
+        list.iter().map(|p| p.to_string()).collect()
+    }
+
+    fn album() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "empress-folder-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("CD1")).unwrap();
+        fs::create_dir_all(dir.join("Scans")).unwrap();
+        dir
+    }
+
+    #[test]
+    fn patterns_are_tried_in_order() {
+        let dir = album();
+        fs::write(dir.join("CD1/Cover.JPG"), b"").unwrap();
+        fs::write(dir.join("CD1/front.png"), b"").unwrap();
+        fs::write(dir.join("CD1/front.txt"), b"").unwrap();
+
+        let search = FolderSearch::new(&patterns(&["front.*", "cover.*"]), 0);
+        assert_eq!(
+            search.find(&dir.join("CD1/01.flac")),
+            Some(dir.join("CD1/front.png"))
+        );
+
+        let search = FolderSearch::new(&patterns(&["cover.*", "front.*"]), 0);
+        assert_eq!(
+            search.find(&dir.join("CD1/01.flac")),
+            Some(dir.join("CD1/Cover.JPG"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parents_are_searched_up_to_depth() {
+        let dir = album();
+        fs::write(dir.join("Scans/Front.webp"), b"").unwrap();
+        let track = dir.join("CD1/01.flac");
+
+        let search = FolderSearch::new(&patterns(&["Scans/front.*"]), 0);
+        assert_eq!(search.find(&track), None);
+
+        let search = FolderSearch::new(&patterns(&["Scans/front.*"]), 1);
+        assert_eq!(search.find(&track), Some(dir.join("Scans/Front.webp")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_files_invalidate_the_cache() {
+        let dir = album();
+        let track = dir.join("CD1/01.flac");
+        let search = FolderSearch::new(&patterns(&["folder.*"]), 0);
+        assert_eq!(search.find(&track), None);
+
+        // Directory mtimes can have a coarse resolution
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        fs::write(dir.join("CD1/folder.jpg"), b"").unwrap();
+        assert_eq!(search.find(&track), Some(dir.join("CD1/folder.jpg")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn new_files_in_subfolders_invalidate_the_cache() {
+        let dir = album();
+        let track = dir.join("01.flac");
+        let search = FolderSearch::new(&patterns(&["Scans/front.*"]), 0);
+        assert_eq!(search.find(&track), None);
+
+        std::thread::sleep(std::time::Duration::from_millis(1100));
+        fs::write(dir.join("Scans/front.jpg"), b"").unwrap();
+        assert_eq!(search.find(&track), Some(dir.join("Scans/front.jpg")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_directories_are_searched() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let dir = album();
+        let album = dir.join(std::ffi::OsStr::from_bytes(b"caf\xe9"));
+        fs::create_dir_all(&album).unwrap();
+        fs::write(album.join("cover.png"), b"").unwrap();
+
+        let search = FolderSearch::new(&patterns(&["cover.*"]), 0);
+        assert_eq!(
+            search.find(&album.join("01.flac")),
+            Some(album.join("cover.png"))
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,256 @@
+//! Album art lookup for `mpris:artUrl`.
+mod cache;
+mod embedded;
+mod folder;
+#[cfg(feature = "thumbnail")]
+mod thumbnail;
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, Receiver, Sender},
+        Arc, Mutex,
+    },
+    time::SystemTime,
+};
+
+use crate::{
+    backend::{Backend, Track},
+    settings::ArtSettings,
+    uri::file_uri,
+    warn,
+};
+
+use cache::ArtCache;
+use folder::FolderSearch;
+
+/// Files whose embedded art is remembered before the memo is emptied.
+const MAX_EXTRACTED_FILES: usize = 256;
+
+/// Finds the cover for a track.
+///
+/// DeaDBeeF's artwork plugin is asked first, so the cover matches the one
+/// the player shows. Its answer arrives later, so until then, or when the
+/// plugin is unavailable, an image next to the file or in the file's own
+/// tags is used. Large covers can be replaced by cached thumbnails.
+pub(crate) struct ArtFinder {
+    plugin: bool,
+    folder: FolderSearch,
+    embedded: bool,
+    /// Cached copy of each file's embedded art, valid while the file's
+    /// mtime is unchanged
+    extracted: RefCell<HashMap<PathBuf, (Option<SystemTime>, Option<PathBuf>)>>,
+    cache: Option<ArtCache>,
+    #[cfg(feature = "thumbnail")]
+    thumbnail_size: Option<u32>,
+    /// Latest cover from the artwork plugin, with its track's id
+    plugin_cover: Arc<Mutex<Option<(usize, PathBuf)>>>,
+    arrived_tx: Sender<usize>,
+    arrived_rx: Receiver<usize>,
+}
+
+impl ArtFinder {
+    pub fn new(settings: &ArtSettings) -> Self {
+        let cache = settings
+            .cache_dir
+            .clone()
+            .or_else(ArtCache::default_dir)
+            .map(|dir| ArtCache::new(dir, settings.cache_max_bytes));
+
+        let (arrived_tx, arrived_rx) = mpsc::channel();
+
+        #[cfg(not(feature = "thumbnail"))]
+        if settings.thumbnail_size.is_some() {
+            warn!("thumbnails are not supported by this build");
+        }
+
+        Self {
+            plugin: settings.plugin,
+            folder: FolderSearch::new(&settings.patterns, settings.search_depth),
+            embedded: settings.embedded,
+            extracted: RefCell::new(HashMap::new()),
+            cache,
+            #[cfg(feature = "thumbnail")]
+            thumbnail_size: settings.thumbnail_size,
+            plugin_cover: Arc::new(Mutex::new(None)),
+            arrived_tx,
+            arrived_rx,
+        }
+    }
+
+    /// Returns the URI of the cover image for `track`.
+    ///
+    /// Only tracks that are local files, at `track_path`, can have art
+    /// next to them or in their tags.
+    pub fn find(&self, track: &Track, track_path: Option<&Path>) -> Option<String> {
+        let cover = self.plugin_cover(track).or_else(|| {
+            let track_path = track_path?;
+            self.folder
+                .find(track_path)
+                .or_else(|| self.embedded_art(track_path))
+        })?;
+
+        Some(file_uri(&self.thumbnail(cover)))
+    }
+
+    /// Starts an artwork plugin lookup for `track`.
+    ///
+    /// When a cover is found, the track's id is reported by
+    /// [`ArtFinder::arrived`] and [`ArtFinder::find`] returns the cover.
+    pub fn request(&self, db: &dyn Backend, track: &Track) {
+        if !self.plugin || self.plugin_cover(track).is_some() {
+            return;
+        }
+
+        let id = track.id;
+        let plugin_cover = Arc::clone(&self.plugin_cover);
+        let arrived = self.arrived_tx.clone();
+
+        db.request_cover(
+            track,
+            Box::new(move |path| {
+                let path = match path {
+                    Some(p) => p,
+                    None => return,
+                };
+                *plugin_cover.lock().unwrap_or_else(|e| e.into_inner()) = Some((id, path));
+                // The receiver is gone once the service shuts down
+                let _ = arrived.send(id);
+            }),
+        );
+    }
+
+    /// Returns the ids of tracks whose plugin cover arrived since the last
+    /// call.
+    pub fn arrived(&self) -> Vec<usize> {
+        self.arrived_rx.try_iter().collect()
+    }
+
+    fn plugin_cover(&self, track: &Track) -> Option<PathBuf> {
+        match &*self.plugin_cover.lock().unwrap_or_else(|e| e.into_inner()) {
+            Some((id, path)) if *id == track.id => Some(path.clone()),
+            _ => None,
+        }
+    }
+
+    /// Swaps `cover` for a cached thumbnail, if thumbnails are enabled.
+    #[cfg(feature = "thumbnail")]
+    fn thumbnail(&self, cover: PathBuf) -> PathBuf {
+        let (cache, max_size) = match (&self.cache, self.thumbnail_size) {
+            (Some(cache), Some(max_size)) => (cache, max_size),
+            _ => return cover,
+        };
+
+        match thumbnail::thumbnail(cache, &cover, max_size) {
+            Ok(thumb) => thumb,
+            Err(e) => {
+                warn!("unable to create a thumbnail of {:?}: {}", cover, e);
+                cover
+            }
+        }
+    }
+
+    #[cfg(not(feature = "thumbnail"))]
+    fn thumbnail(&self, cover: PathBuf) -> PathBuf {
+        cover
+    }
+
+    /// Extracts the picture embedded in the file's tags into the cache,
+    /// once per version of the file.
+    fn embedded_art(&self, track_path: &Path) -> Option<PathBuf> {
+        let cache = self.cache.as_ref().filter(|_| self.embedded)?;
+        let modified = std::fs::metadata(track_path)
+            .and_then(|m| m.modified())
+            .ok();
+
+        if let Some((extracted_at, found)) = self.extracted.borrow().get(track_path) {
+            // The copy may have been trimmed from the cache since
+            if *extracted_at == modified && found.as_ref().map_or(true, |p| p.is_file()) {
+                return found.clone();
+            }
+        }
+
+        let found = Self::extract(cache, track_path);
+
+        let mut extracted = self.extracted.borrow_mut();
+        if extracted.len() >= MAX_EXTRACTED_FILES {
+            extracted.clear();
+        }
+        extracted.insert(track_path.to_path_buf(), (modified, found.clone()));
+
+        found
+    }
+
+    fn extract(cache: &ArtCache, track_path: &Path) -> Option<PathBuf> {
+        let picture = match embedded::extract(track_path) {
+            Ok(p) => p?,
+            Err(e) => {
+                warn!("unable to read embedded art from {:?}: {}", track_path, e);
+                return None;
+            }
+        };
+
+        match cache.store(picture.ext, &picture.data) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                warn!("unable to cache embedded art: {}", e);
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    const PNG: &[u8] = &[0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, 1, 2, 3];
+
+    /// An MP3 with `PNG` as its front cover.
+    fn tagged() -> Vec<u8> {
+        let body = [&[0u8][..], b"image/png\0", &[3], b"\0", PNG].concat();
+        let frame = [
+            &b"APIC"[..],
+            &(body.len() as u32).to_be_bytes(),
+            &[0, 0],
+            &body,
+        ]
+        .concat();
+        let header = [b'I', b'D', b'3', 3, 0, 0, 0, 0, 0, frame.len() as u8];
+        [&header[..], &frame].concat()
+    }
+
+    #[test]
+    fn embedded_art_is_extracted_once() {
+        let dir = std::env::temp_dir().join(format!("empress-art-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let track = dir.join("01.mp3");
+        fs::write(&track, tagged()).unwrap();
+        let finder = ArtFinder::new(&ArtSettings {
+            plugin: false,
+            patterns: Vec::new(),
+            cache_dir: Some(dir.join("cache")),
+            ..Default::default()
+        });
+
+        let cover = finder.embedded_art(&track).unwrap();
+        assert_eq!(fs::read(&cover).unwrap(), PNG);
+
+        // Same mtime, so the file is not read again
+        let modified = fs::metadata(&track).unwrap().modified().unwrap();
+        fs::write(&track, b"untagged").unwrap();
+        let file = fs::File::options().write(true).open(&track).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(finder.embedded_art(&track), Some(cover.clone()));
+
+        // A copy trimmed from the cache is extracted again
+        fs::remove_file(&cover).unwrap();
+        assert_eq!(finder.embedded_art(&track), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
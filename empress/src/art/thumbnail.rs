@@ -0,0 +1,133 @@
+//! Downscaled copies of large covers, so desktop shells don't have to
+//! decode the original on every track change.
+use std::{
+    fs,
+    io::{self, Cursor},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use image::{DynamicImage, ImageFormat, ImageOutputFormat};
+
+use super::cache::{fnv1a, ArtCache};
+
+/// Quality of the JPEG thumbnails.
+const JPEG_QUALITY: u8 = 85;
+
+/// Formats every desktop shell can display without help.
+const PASSTHROUGH: &[ImageFormat] = &[ImageFormat::Jpeg, ImageFormat::Png];
+
+/// Returns a JPEG or PNG no larger than `max_size` pixels on either side
+/// for the image at `source`.
+///
+/// Covers that already fit are used as they are. Otherwise the thumbnail
+/// is cached under the source's path, size and modification time, so it is
+/// reused until the source changes.
+pub(crate) fn thumbnail(cache: &ArtCache, source: &Path, max_size: u32) -> io::Result<PathBuf> {
+    let meta = fs::metadata(source)?;
+    let modified = meta
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0);
+    let key = format!(
+        "thumb-{:016x}",
+        fnv1a(
+            format!(
+                "{}\0{}\0{}\0{}",
+                source.display(),
+                meta.len(),
+                modified,
+                max_size
+            )
+            .as_bytes()
+        )
+    );
+
+    if let Some(path) = cache.get(&key, &["jpg", "png"]) {
+        return Ok(path);
+    }
+
+    let format = ImageFormat::from_path(source).ok();
+    if format.map_or(false, |f| PASSTHROUGH.contains(&f)) {
+        let (width, height) = image::image_dimensions(source).map_err(to_io)?;
+        if width <= max_size && height <= max_size {
+            return Ok(source.to_path_buf());
+        }
+    }
+
+    let image = image::io::Reader::open(source)?
+        .with_guessed_format()?
+        .decode()
+        .map_err(to_io)?;
+    let (ext, data) = encode(&image.thumbnail(max_size, max_size))?;
+
+    cache.store_as(&key, ext, &data)
+}
+
+/// Encodes `image` as a JPEG, or as a PNG when it has transparency.
+fn encode(image: &DynamicImage) -> io::Result<(&'static str, Vec<u8>)> {
+    let mut data = Cursor::new(Vec::new());
+
+    let ext = if image.color().has_alpha() {
+        image
+            .write_to(&mut data, ImageOutputFormat::Png)
+            .map_err(to_io)?;
+        "png"
+    } else {
+        DynamicImage::ImageRgb8(image.to_rgb8())
+            .write_to(&mut data, ImageOutputFormat::Jpeg(JPEG_QUALITY))
+            .map_err(to_io)?;
+        "jpg"
+    };
+
+    Ok((ext, data.into_inner()))
+}
+
+fn to_io(e: image::ImageError) -> io::Error {
+    match e {
+        image::ImageError::IoError(e) => e,
+        e => io::Error::new(io::ErrorKind::InvalidData, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("empress-thumb-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn large_covers_are_downscaled_once() {
+        let dir = scratch("large");
+        let source = dir.join("cover.png");
+        RgbImage::new(64, 32).save(&source).unwrap();
+        let cache = ArtCache::new(dir.join("cache"), 1024 * 1024);
+
+        let thumb = thumbnail(&cache, &source, 16).unwrap();
+        assert_eq!(thumb.extension().unwrap(), "jpg");
+        assert_eq!(image::image_dimensions(&thumb).unwrap(), (16, 8));
+
+        assert_eq!(thumbnail(&cache, &source, 16).unwrap(), thumb);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn small_covers_are_used_as_they_are() {
+        let dir = scratch("small");
+        let source = dir.join("cover.png");
+        RgbImage::new(8, 8).save(&source).unwrap();
+        let cache = ArtCache::new(dir.join("cache"), 1024 * 1024);
+
+        assert_eq!(thumbnail(&cache, &source, 16).unwrap(), source);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
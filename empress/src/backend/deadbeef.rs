@@ -0,0 +1,329 @@
+use std::{
+    sync::{Mutex, MutexGuard},
+    time::Duration,
+};
+
+use crate::{
+    deadbeef::{
+        Command, CoverCallback, Deadbeef, Error, Feature, OutputState, TitleFormat, TrackRef,
+    },
+    rating::{self, RatingTag},
+    settings::TitleFormats,
+    uri::Location,
+    warn,
+};
+
+use super::{Backend, Extras, Formatted, ReplayGainMode, Track};
+
+const STOP_AFTER_CURRENT: &str = "playlist.stop_after_current";
+const STOP_AFTER_ALBUM: &str = "playlist.stop_after_album";
+const REPLAYGAIN_SOURCE: &str = "replaygain.source_mode";
+const REPLAYGAIN_PROCESSING: &str = "replaygain.processing_flags";
+
+/// Values of `replaygain.source_mode`.
+const SOURCE_PLAYBACK_ORDER: i32 = 0;
+const SOURCE_TRACK: i32 = 1;
+const SOURCE_ALBUM: i32 = 2;
+
+/// Bit of `replaygain.processing_flags` that applies the gain. The other
+/// bits, such as clipping prevention, are left alone.
+const PROCESSING_GAIN: i32 = 1;
+
+/// [`Backend`] for the running DeaDBeeF instance.
+pub struct DeadbeefBackend {
+    db: Deadbeef,
+    formats: Mutex<Compiled>,
+    rating_tags: Mutex<Vec<RatingTag>>,
+}
+
+/// Title-format scripts, with the source they were compiled from.
+#[derive(Default)]
+struct Compiled {
+    scripts: TitleFormats,
+    title: Option<TitleFormat>,
+    artist: Option<TitleFormat>,
+    album: Option<TitleFormat>,
+    notification: Option<TitleFormat>,
+}
+
+impl DeadbeefBackend {
+    pub fn new(db: Deadbeef) -> Self {
+        Self {
+            db,
+            formats: Mutex::new(Compiled::default()),
+            rating_tags: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn formats(&self) -> MutexGuard<'_, Compiled> {
+        self.formats.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn rating_tags(&self) -> MutexGuard<'_, Vec<RatingTag>> {
+        self.rating_tags.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Compiles `script`, or returns `None` so the raw tags are used if it
+    /// is empty or the player has no title formats.
+    fn compile(&self, script: &str) -> Result<Option<TitleFormat>, Error> {
+        if script.trim().is_empty() {
+            return Ok(None);
+        }
+        if !self.has(Feature::TitleFormat) {
+            warn!(
+                "ignoring title format {:?}: {}",
+                script,
+                Error::Unsupported(Feature::TitleFormat)
+            );
+            return Ok(None);
+        }
+        let compiled = self.db.tf_compile(script)?;
+        if compiled.is_none() {
+            warn!("invalid title format {:?}", script);
+        }
+        Ok(compiled)
+    }
+
+    fn format(&self, track: &TrackRef) -> Formatted {
+        let formats = self.formats();
+        let eval = |format: &Option<TitleFormat>| {
+            let format = format.as_ref()?;
+            match format.eval(track) {
+                Ok(text) if !text.is_empty() => Some(text),
+                Ok(_) => None,
+                Err(e) => {
+                    warn!("unable to format track: {}", e);
+                    None
+                }
+            }
+        };
+
+        Formatted {
+            title: eval(&formats.title),
+            artist: eval(&formats.artist),
+            album: eval(&formats.album),
+            notification: eval(&formats.notification),
+        }
+    }
+
+    fn snapshot(&self, track: &TrackRef) -> Result<Track, Error> {
+        let metadata = self.db.metadata(track)?;
+        Ok(Track {
+            id: track.id(),
+            rating: rating::read(&self.rating_tags(), &metadata),
+            metadata,
+            formatted: self.format(track),
+        })
+    }
+
+    /// Fails unless the player provides `feature`.
+    fn require(&self, feature: Feature) -> Result<(), Error> {
+        if self.has(feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported(feature))
+        }
+    }
+
+    /// Notifies DeaDBeeF's GUI and other plugins of a queue edit.
+    fn queue_changed(&self) -> Result<(), Error> {
+        self.db.send(Command::PlayQueueChanged)
+    }
+}
+
+impl Backend for DeadbeefBackend {
+    fn has(&self, feature: Feature) -> bool {
+        self.db.capabilities().has(feature)
+    }
+
+    fn send(&self, cmd: Command) -> Result<(), Error> {
+        self.db.send(cmd)
+    }
+
+    fn pause_output(&self) -> Result<(), Error> {
+        self.db.pause_output()
+    }
+
+    fn output_state(&self) -> Result<OutputState, Error> {
+        self.db.output_state()
+    }
+
+    fn shuffle(&self) -> Result<bool, Error> {
+        self.db.shuffle()
+    }
+
+    fn set_shuffle(&self, shuffle: bool) -> Result<(), Error> {
+        self.db.set_shuffle(shuffle)?;
+        // The GUI shows the playback order from the config
+        self.db.send(Command::ConfigChanged)
+    }
+
+    fn position(&self) -> Result<Duration, Error> {
+        let secs = self.db.playback_position()?;
+        Ok(Duration::try_from_secs_f32(secs).unwrap_or_default())
+    }
+
+    fn volume(&self) -> Result<f64, Error> {
+        Ok(self.db.volume()?.into())
+    }
+
+    fn set_volume(&self, volume: f64) -> Result<(), Error> {
+        self.db.set_volume(volume as f32)
+    }
+
+    fn playing_track(&self) -> Result<Option<Track>, Error> {
+        match self.db.playing_track() {
+            Some(track) => Ok(Some(self.snapshot(&track)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn request_cover(&self, track: &Track, done: CoverCallback) -> bool {
+        // Only the playing item can be turned back into a reference
+        let playing = match self.db.playing_track() {
+            Some(t) if t.id() == track.id => t,
+            _ => return false,
+        };
+        match self.db.artwork() {
+            Some(artwork) => artwork.cover(&playing, done),
+            None => false,
+        }
+    }
+
+    fn set_title_formats(&self, formats: &TitleFormats) -> Result<(), Error> {
+        if self.formats().scripts == *formats {
+            return Ok(());
+        }
+
+        let compiled = Compiled {
+            scripts: formats.clone(),
+            title: self.compile(&formats.title)?,
+            artist: self.compile(&formats.artist)?,
+            album: self.compile(&formats.album)?,
+            notification: self.compile(&formats.notification)?,
+        };
+        *self.formats() = compiled;
+        Ok(())
+    }
+
+    fn set_rating_tags(&self, tags: &[RatingTag]) -> Result<(), Error> {
+        *self.rating_tags() = tags.to_vec();
+        Ok(())
+    }
+
+    fn set_rating(&self, id: usize, rating: f64) -> Result<(), Error> {
+        // The playing item may have been removed from its playlist
+        let track = match self.db.playing_track() {
+            Some(track) if track.id() == id => track,
+            _ => self
+                .db
+                .find_track(|track| track.id() == id)?
+                .ok_or(Error::UnknownTrack(id))?,
+        };
+
+        let values = rating::tag_values(&self.rating_tags(), &self.db.metadata(&track)?, rating);
+        if values.is_empty() {
+            warn!("not rating track {}: no rating tags are configured", id);
+            return Ok(());
+        }
+        for (key, value) in &values {
+            self.db.set_meta(&track, key, value)?;
+        }
+        self.db.write_tags(&track)?;
+        self.db.track_info_changed(&track)
+    }
+
+    fn queue(&self) -> Result<Vec<Track>, Error> {
+        self.require(Feature::PlayQueue)?;
+        self.db
+            .playqueue()?
+            .iter()
+            .map(|track| self.snapshot(track))
+            .collect()
+    }
+
+    fn find_track(&self, location: &Location) -> Result<Option<usize>, Error> {
+        let found = self.db.find_track(|track| {
+            matches!(
+                self.db.meta(track, ":URI"),
+                Ok(Some(uri)) if Location::parse(&uri) == *location
+            )
+        })?;
+        Ok(found.map(|track| track.id()))
+    }
+
+    fn enqueue(&self, id: usize) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        let track = self
+            .db
+            .find_track(|track| track.id() == id)?
+            .ok_or(Error::UnknownTrack(id))?;
+        self.db.playqueue_push(&track)?;
+        self.queue_changed()
+    }
+
+    fn dequeue(&self, id: usize) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        let track = self
+            .db
+            .playqueue()?
+            .into_iter()
+            .find(|track| track.id() == id)
+            .ok_or(Error::UnknownTrack(id))?;
+        self.db.playqueue_remove(&track)?;
+        self.queue_changed()
+    }
+
+    fn clear_queue(&self) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        self.db.playqueue_clear()?;
+        self.queue_changed()
+    }
+
+    fn extras(&self) -> Result<Extras, Error> {
+        let replaygain = if self.db.conf_int(REPLAYGAIN_PROCESSING, 0) & PROCESSING_GAIN == 0 {
+            ReplayGainMode::Off
+        } else {
+            match self.db.conf_int(REPLAYGAIN_SOURCE, SOURCE_PLAYBACK_ORDER) {
+                SOURCE_TRACK => ReplayGainMode::Track,
+                SOURCE_ALBUM => ReplayGainMode::Album,
+                _ => ReplayGainMode::PlaybackOrder,
+            }
+        };
+
+        Ok(Extras {
+            stop_after_current: self.db.conf_int(STOP_AFTER_CURRENT, 0) != 0,
+            stop_after_album: self.db.conf_int(STOP_AFTER_ALBUM, 0) != 0,
+            replaygain,
+        })
+    }
+
+    fn set_extras(&self, extras: &Extras) -> Result<(), Error> {
+        self.db
+            .conf_set_int(STOP_AFTER_CURRENT, extras.stop_after_current as i32)?;
+        self.db
+            .conf_set_int(STOP_AFTER_ALBUM, extras.stop_after_album as i32)?;
+
+        let processing = self.db.conf_int(REPLAYGAIN_PROCESSING, 0);
+        let source = match extras.replaygain {
+            ReplayGainMode::Off => None,
+            ReplayGainMode::Track => Some(SOURCE_TRACK),
+            ReplayGainMode::Album => Some(SOURCE_ALBUM),
+            ReplayGainMode::PlaybackOrder => Some(SOURCE_PLAYBACK_ORDER),
+        };
+        match source {
+            Some(source) => {
+                self.db.conf_set_int(REPLAYGAIN_SOURCE, source)?;
+                self.db
+                    .conf_set_int(REPLAYGAIN_PROCESSING, processing | PROCESSING_GAIN)?;
+            }
+            None => {
+                self.db
+                    .conf_set_int(REPLAYGAIN_PROCESSING, processing & !PROCESSING_GAIN)?;
+            }
+        }
+
+        // The streamer and GUI pick up config changes from this message
+        self.db.send(Command::ConfigChanged)
+    }
+}
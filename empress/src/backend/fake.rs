@@ -0,0 +1,255 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::{Arc, Mutex, MutexGuard},
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    deadbeef::{Command, CoverCallback, Error, Feature, OutputState},
+    rating::RatingTag,
+    settings::TitleFormats,
+    uri::Location,
+};
+
+use super::{Backend, Extras, Formatted, Track};
+
+/// Player state held by a [`FakeBackend`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FakeState {
+    pub output_state: OutputState,
+    pub shuffle: bool,
+    pub position: Duration,
+    pub volume: f64,
+    pub playing: Option<Track>,
+    /// Tracks in the player's playlists
+    pub library: Vec<Track>,
+    /// Tracks in the play queue, in play order
+    pub queue: Vec<Track>,
+    /// Covers the artwork plugin reports, by track id
+    pub covers: HashMap<usize, PathBuf>,
+    pub extras: Extras,
+    /// Scripts set by the service; tracks carry their own formatted tags
+    pub title_formats: TitleFormats,
+    /// Rating tags set by the service; tracks carry their own rating
+    pub rating_tags: Vec<RatingTag>,
+    /// Every command sent to the player, oldest first
+    pub commands: Vec<Command>,
+    /// When set, every call fails with this error
+    pub error: Option<Error>,
+    /// Features the player lacks, as an older DeaDBeeF would
+    pub missing: Vec<Feature>,
+}
+
+impl Default for FakeState {
+    fn default() -> Self {
+        Self {
+            output_state: OutputState::Stopped,
+            shuffle: false,
+            position: Duration::ZERO,
+            volume: 1.0,
+            playing: None,
+            library: Vec::new(),
+            queue: Vec::new(),
+            covers: HashMap::new(),
+            extras: Extras::default(),
+            title_formats: TitleFormats::default(),
+            rating_tags: Vec::new(),
+            commands: Vec::new(),
+            error: None,
+            missing: Vec::new(),
+        }
+    }
+}
+
+/// In-memory [`Backend`] for running the MPRIS service without DeaDBeeF.
+///
+/// Clones share the same state, so a test can keep one handle to script
+/// the player while the service owns another.
+#[derive(Debug, Clone, Default)]
+pub struct FakeBackend {
+    state: Arc<Mutex<FakeState>>,
+}
+
+impl FakeBackend {
+    pub fn new(state: FakeState) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(state)),
+        }
+    }
+
+    /// Locks the shared player state for inspection or modification.
+    pub fn state(&self) -> MutexGuard<'_, FakeState> {
+        // A panicking test must not hide the state from the others
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn checked(&self) -> Result<MutexGuard<'_, FakeState>, Error> {
+        let state = self.state();
+        match &state.error {
+            Some(e) => Err(e.clone()),
+            None => Ok(state),
+        }
+    }
+
+    /// As [`FakeBackend::checked`], but also fails if `feature` is missing.
+    fn supporting(&self, feature: Feature) -> Result<MutexGuard<'_, FakeState>, Error> {
+        let state = self.checked()?;
+        if state.missing.contains(&feature) {
+            return Err(Error::Unsupported(feature));
+        }
+        Ok(state)
+    }
+
+    /// `track` as the player reports it, without formatted tags when it
+    /// has no title formats.
+    fn snapshot(&self, state: &FakeState, track: &Track) -> Track {
+        let mut track = track.clone();
+        if state.missing.contains(&Feature::TitleFormat) {
+            track.formatted = Formatted::default();
+        }
+        track
+    }
+}
+
+impl Backend for FakeBackend {
+    fn has(&self, feature: Feature) -> bool {
+        !self.state().missing.contains(&feature)
+    }
+
+    fn send(&self, cmd: Command) -> Result<(), Error> {
+        self.checked()?.commands.push(cmd);
+        Ok(())
+    }
+
+    fn pause_output(&self) -> Result<(), Error> {
+        self.checked()?.output_state = OutputState::Paused;
+        Ok(())
+    }
+
+    fn output_state(&self) -> Result<OutputState, Error> {
+        Ok(self.checked()?.output_state)
+    }
+
+    fn shuffle(&self) -> Result<bool, Error> {
+        Ok(self.supporting(Feature::Shuffle)?.shuffle)
+    }
+
+    fn set_shuffle(&self, shuffle: bool) -> Result<(), Error> {
+        self.supporting(Feature::Shuffle)?.shuffle = shuffle;
+        Ok(())
+    }
+
+    fn position(&self) -> Result<Duration, Error> {
+        Ok(self.checked()?.position)
+    }
+
+    fn volume(&self) -> Result<f64, Error> {
+        Ok(self.checked()?.volume)
+    }
+
+    fn set_volume(&self, volume: f64) -> Result<(), Error> {
+        self.checked()?.volume = volume;
+        Ok(())
+    }
+
+    fn playing_track(&self) -> Result<Option<Track>, Error> {
+        let state = self.checked()?;
+        Ok(state
+            .playing
+            .as_ref()
+            .map(|track| self.snapshot(&state, track)))
+    }
+
+    fn request_cover(&self, track: &Track, done: CoverCallback) -> bool {
+        let cover = match self.checked() {
+            Ok(state) => state.covers.get(&track.id).cloned(),
+            Err(_) => return false,
+        };
+        // Like the artwork plugin, answer from another thread
+        thread::spawn(move || done(cover));
+        true
+    }
+
+    fn set_title_formats(&self, formats: &TitleFormats) -> Result<(), Error> {
+        self.checked()?.title_formats = formats.clone();
+        Ok(())
+    }
+
+    fn set_rating_tags(&self, tags: &[RatingTag]) -> Result<(), Error> {
+        self.checked()?.rating_tags = tags.to_vec();
+        Ok(())
+    }
+
+    fn set_rating(&self, id: usize, rating: f64) -> Result<(), Error> {
+        let mut state = self.checked()?;
+        let state = &mut *state;
+        let mut found = false;
+        for track in state.playing.iter_mut().chain(state.library.iter_mut()) {
+            if track.id == id {
+                track.rating = Some(rating);
+                found = true;
+            }
+        }
+        if found {
+            Ok(())
+        } else {
+            Err(Error::UnknownTrack(id))
+        }
+    }
+
+    fn queue(&self) -> Result<Vec<Track>, Error> {
+        let state = self.supporting(Feature::PlayQueue)?;
+        Ok(state
+            .queue
+            .iter()
+            .map(|track| self.snapshot(&state, track))
+            .collect())
+    }
+
+    fn find_track(&self, location: &Location) -> Result<Option<usize>, Error> {
+        let state = self.checked()?;
+        let found = state.library.iter().find(|track| {
+            track.metadata.iter().any(|(key, uri)| {
+                key.eq_ignore_ascii_case(":uri") && Location::parse(uri) == *location
+            })
+        });
+        Ok(found.map(|track| track.id))
+    }
+
+    fn enqueue(&self, id: usize) -> Result<(), Error> {
+        let mut state = self.supporting(Feature::PlayQueue)?;
+        let track = state
+            .library
+            .iter()
+            .find(|track| track.id == id)
+            .cloned()
+            .ok_or(Error::UnknownTrack(id))?;
+        state.queue.push(track);
+        Ok(())
+    }
+
+    fn dequeue(&self, id: usize) -> Result<(), Error> {
+        let mut state = self.supporting(Feature::PlayQueue)?;
+        if !state.queue.iter().any(|track| track.id == id) {
+            return Err(Error::UnknownTrack(id));
+        }
+        state.queue.retain(|track| track.id != id);
+        Ok(())
+    }
+
+    fn clear_queue(&self) -> Result<(), Error> {
+        self.supporting(Feature::PlayQueue)?.queue.clear();
+        Ok(())
+    }
+
+    fn extras(&self) -> Result<Extras, Error> {
+        Ok(self.checked()?.extras)
+    }
+
+    fn set_extras(&self, extras: &Extras) -> Result<(), Error> {
+        self.checked()?.extras = *extras;
+        Ok(())
+    }
+}
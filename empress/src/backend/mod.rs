@@ -0,0 +1,131 @@
+//! The player operations the MPRIS service relies on.
+//!
+//! [`DeadbeefBackend`] implements [`Backend`] against the live player, while
+//! [`FakeBackend`] keeps scripted state in memory so the service can run
+//! without DeaDBeeF.
+mod deadbeef;
+mod fake;
+
+use std::time::Duration;
+
+pub use deadbeef::DeadbeefBackend;
+pub use fake::{FakeBackend, FakeState};
+
+use crate::{
+    deadbeef::{Command, CoverCallback, Error, Feature, OutputState},
+    rating::RatingTag,
+    settings::TitleFormats,
+    uri::Location,
+};
+
+/// Snapshot of a playlist item and its metadata.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Track {
+    /// Identifier of the item, stable for as long as it is loaded
+    pub id: usize,
+    /// Tags and DeaDBeeF properties, in the player's order
+    pub metadata: Vec<(String, String)>,
+    /// Output of the configured title-format scripts
+    pub formatted: Formatted,
+    /// Rating from 0.0 to 1.0, read from the configured rating tags
+    pub rating: Option<f64>,
+}
+
+/// Tags rendered by title-format scripts, replacing the raw values.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Formatted {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    /// Text of track change notifications
+    pub notification: Option<String>,
+}
+
+/// Playback options DeaDBeeF has beyond those in MPRIS.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Extras {
+    pub stop_after_current: bool,
+    pub stop_after_album: bool,
+    pub replaygain: ReplayGainMode,
+}
+
+/// Which ReplayGain values are applied, if any.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ReplayGainMode {
+    #[default]
+    Off,
+    Track,
+    Album,
+    /// Album gain when playing in order, track gain when shuffling
+    PlaybackOrder,
+}
+
+pub trait Backend {
+    /// Whether the player provides `feature`. Calls needing a missing
+    /// feature fail with [`Error::Unsupported`].
+    fn has(&self, feature: Feature) -> bool;
+
+    /// Posts `cmd` to the player.
+    fn send(&self, cmd: Command) -> Result<(), Error>;
+
+    /// Pauses the output device directly.
+    fn pause_output(&self) -> Result<(), Error>;
+
+    /// Returns the playback state of the output device.
+    fn output_state(&self) -> Result<OutputState, Error>;
+
+    /// Whether any shuffle mode is active.
+    fn shuffle(&self) -> Result<bool, Error>;
+
+    /// Turns shuffle on or off.
+    fn set_shuffle(&self, shuffle: bool) -> Result<(), Error>;
+
+    /// Position in the playing track.
+    fn position(&self) -> Result<Duration, Error>;
+
+    /// Output volume, from 0.0 (silent) to 1.0 (full).
+    fn volume(&self) -> Result<f64, Error>;
+
+    fn set_volume(&self, volume: f64) -> Result<(), Error>;
+
+    /// Returns the track currently being played, if any.
+    fn playing_track(&self) -> Result<Option<Track>, Error>;
+
+    /// Asks the player's artwork plugin for the cover of `track`.
+    ///
+    /// Returns `false` if no lookup was started. Otherwise `done` is called
+    /// once the lookup finishes, possibly on another thread.
+    fn request_cover(&self, track: &Track, done: CoverCallback) -> bool;
+
+    /// Sets the title-format scripts applied by [`Backend::playing_track`].
+    fn set_title_formats(&self, formats: &TitleFormats) -> Result<(), Error>;
+
+    /// Sets the tags [`Track::rating`] is read from and
+    /// [`Backend::set_rating`] writes.
+    fn set_rating_tags(&self, tags: &[RatingTag]) -> Result<(), Error>;
+
+    /// Rates the track `id` from 0.0 to 1.0, saving the rating to its file.
+    fn set_rating(&self, id: usize, rating: f64) -> Result<(), Error>;
+
+    /// Returns the tracks in the play queue, in the order they will play.
+    fn queue(&self) -> Result<Vec<Track>, Error>;
+
+    /// Returns the id of a track at `location` in any playlist.
+    fn find_track(&self, location: &Location) -> Result<Option<usize>, Error>;
+
+    /// Adds the track `id`, which must be in a playlist, to the end of the
+    /// play queue.
+    fn enqueue(&self, id: usize) -> Result<(), Error>;
+
+    /// Removes the track `id` from the play queue.
+    fn dequeue(&self, id: usize) -> Result<(), Error>;
+
+    /// Empties the play queue.
+    fn clear_queue(&self) -> Result<(), Error>;
+
+    /// Returns the current playback options.
+    fn extras(&self) -> Result<Extras, Error>;
+
+    /// Changes the playback options.
+    fn set_extras(&self, extras: &Extras) -> Result<(), Error>;
+}
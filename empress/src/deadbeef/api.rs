@@ -0,0 +1,422 @@
+//! Safe wrapper over the DeaDBeeF plugin API.
+//!
+//! All calls through `DB_functions_t` live here, so the rest of the crate
+//! never has to touch raw pointers or `unsafe`.
+use std::{
+    ffi::{CStr, CString},
+    fmt,
+    os::raw::c_char,
+};
+
+use super::{
+    bindings::{self, DB_functions_t, DB_playItem_t},
+    capabilities::{ApiVersion, Capabilities, Feature},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The host does not provide the named API function
+    Missing(&'static str),
+    /// The named API function returned a null pointer
+    Null(&'static str),
+    /// The named API function reported a failure
+    Failed(&'static str),
+    /// The host's API is too old for the feature
+    Unsupported(Feature),
+    /// No loaded playlist item has the given id
+    UnknownTrack(usize),
+    /// No decoder can save the tags of the track with the given id, as for
+    /// streams
+    ReadOnly(usize),
+    /// The output plugin reported an unknown playback state
+    InvalidState(u32),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Missing(name) => write!(f, "unable to get {} function", name),
+            Error::Null(name) => write!(f, "null value returned by {}", name),
+            Error::Failed(name) => write!(f, "{} failed", name),
+            Error::Unsupported(feature) => {
+                write!(f, "{} not supported by this DeaDBeeF", feature)
+            }
+            Error::UnknownTrack(id) => write!(f, "no loaded track has id {}", id),
+            Error::ReadOnly(id) => write!(f, "tags of track {} cannot be saved", id),
+            Error::InvalidState(state) => write!(f, "invalid playback state: {}", state),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub(super) fn func<T>(f: Option<T>, name: &'static str) -> Result<T, Error> {
+    f.ok_or(Error::Missing(name))
+}
+
+/// Commands that can be sent to the player.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    Next,
+    Previous,
+    PlayCurrent,
+    Pause,
+    TogglePause,
+    Stop,
+    PlayNextAlbum,
+    PlayPrevAlbum,
+    PlayRandomAlbum,
+    /// Tells every plugin, including this one, that the config changed.
+    ConfigChanged,
+    /// Tells every plugin, including this one, that the play queue changed.
+    PlayQueueChanged,
+    /// Seeks the playing track to the position, in milliseconds.
+    Seek(u32),
+}
+
+impl Command {
+    /// The message id and its `p1` argument.
+    fn message(self) -> (u32, u32) {
+        let id = match self {
+            Command::Next => bindings::DB_EV_NEXT,
+            Command::Previous => bindings::DB_EV_PREV,
+            Command::PlayCurrent => bindings::DB_EV_PLAY_CURRENT,
+            Command::Pause => bindings::DB_EV_PAUSE,
+            Command::TogglePause => bindings::DB_EV_TOGGLE_PAUSE,
+            Command::Stop => bindings::DB_EV_STOP,
+            Command::PlayNextAlbum => bindings::DB_EV_PLAY_NEXT_ALBUM,
+            Command::PlayPrevAlbum => bindings::DB_EV_PLAY_PREV_ALBUM,
+            Command::PlayRandomAlbum => bindings::DB_EV_PLAY_RANDOM_ALBUM,
+            Command::ConfigChanged => bindings::DB_EV_CONFIGCHANGED,
+            Command::PlayQueueChanged => {
+                return (
+                    bindings::DB_EV_PLAYLISTCHANGED,
+                    bindings::DDB_PLAYLIST_CHANGE_PLAYQUEUE,
+                )
+            }
+            Command::Seek(ms) => return (bindings::DB_EV_SEEK, ms),
+        };
+        (id, 0)
+    }
+}
+
+/// Playback state of the active output plugin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputState {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+impl TryFrom<u32> for OutputState {
+    type Error = Error;
+
+    fn try_from(state: u32) -> Result<Self, Self::Error> {
+        match state {
+            bindings::DDB_PLAYBACK_STATE_STOPPED => Ok(OutputState::Stopped),
+            bindings::DDB_PLAYBACK_STATE_PLAYING => Ok(OutputState::Playing),
+            bindings::DDB_PLAYBACK_STATE_PAUSED => Ok(OutputState::Paused),
+            _ => Err(Error::InvalidState(state)),
+        }
+    }
+}
+
+/// Handle to the DeaDBeeF API table passed to the plugin on load.
+#[derive(Clone, Copy)]
+pub struct Deadbeef {
+    api: &'static DB_functions_t,
+    capabilities: Capabilities,
+}
+
+impl Deadbeef {
+    pub fn new(api: &'static DB_functions_t) -> Self {
+        Self {
+            api,
+            capabilities: Capabilities::new(ApiVersion::of(api)),
+        }
+    }
+
+    /// The raw API table, for the sibling modules wrapping other plugins.
+    pub(super) fn api(&self) -> &'static DB_functions_t {
+        self.api
+    }
+
+    /// The features this host provides.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Fails unless the host provides `feature`, whose functions may lie
+    /// beyond the end of an older host's API table.
+    pub(super) fn require(&self, feature: Feature) -> Result<(), Error> {
+        if self.capabilities.has(feature) {
+            Ok(())
+        } else {
+            Err(Error::Unsupported(feature))
+        }
+    }
+
+    /// Posts `cmd` to the player's message queue.
+    pub fn send(&self, cmd: Command) -> Result<(), Error> {
+        let sendmessage_fn = func(self.api.sendmessage, "sendmessage")?;
+        let (id, p1) = cmd.message();
+        unsafe { sendmessage_fn(id, 0, p1, 0) };
+        Ok(())
+    }
+
+    /// Returns the playback state reported by the active output plugin.
+    pub fn output_state(&self) -> Result<OutputState, Error> {
+        let output = self.output()?;
+        let state_fn = func(output.state, "output state")?;
+        OutputState::try_from(unsafe { state_fn() })
+    }
+
+    /// Pauses the active output plugin directly.
+    pub fn pause_output(&self) -> Result<(), Error> {
+        let output = self.output()?;
+        let pause_fn = func(output.pause, "output pause")?;
+        unsafe { pause_fn() };
+        Ok(())
+    }
+
+    fn output(&self) -> Result<&'static bindings::DB_output_t, Error> {
+        let output_fn = func(self.api.get_output, "get_output")?;
+        unsafe { output_fn().as_ref() }.ok_or(Error::Null("get_output"))
+    }
+
+    /// Whether any shuffle mode is active.
+    pub fn shuffle(&self) -> Result<bool, Error> {
+        self.require(Feature::Shuffle)?;
+        let shuffle_fn = func(self.api.streamer_get_shuffle, "streamer_get_shuffle")?;
+        Ok(unsafe { shuffle_fn() } > 0)
+    }
+
+    /// Turns shuffle on or off. Turning it on keeps the current mode, such
+    /// as shuffling albums, and otherwise shuffles tracks.
+    pub fn set_shuffle(&self, shuffle: bool) -> Result<(), Error> {
+        self.require(Feature::Shuffle)?;
+        let set_shuffle_fn = func(self.api.streamer_set_shuffle, "streamer_set_shuffle")?;
+        if shuffle == self.shuffle()? {
+            return Ok(());
+        }
+        let mode = if shuffle {
+            bindings::DDB_SHUFFLE_TRACKS
+        } else {
+            bindings::DDB_SHUFFLE_OFF
+        };
+        unsafe { set_shuffle_fn(mode) };
+        Ok(())
+    }
+
+    /// Position in the playing track, in seconds.
+    pub fn playback_position(&self) -> Result<f32, Error> {
+        let playpos_fn = func(self.api.streamer_get_playpos, "streamer_get_playpos")?;
+        Ok(unsafe { playpos_fn() })
+    }
+
+    /// Output volume as a linear amplitude, from 0.0 to 1.0.
+    pub fn volume(&self) -> Result<f32, Error> {
+        let get_amp_fn = func(self.api.volume_get_amp, "volume_get_amp")?;
+        Ok(unsafe { get_amp_fn() })
+    }
+
+    pub fn set_volume(&self, amp: f32) -> Result<(), Error> {
+        let set_amp_fn = func(self.api.volume_set_amp, "volume_set_amp")?;
+        unsafe { set_amp_fn(amp) };
+        Ok(())
+    }
+
+    /// Returns the track currently being played, if any.
+    pub fn playing_track(&self) -> Option<TrackRef> {
+        let get_track_fn = self.api.streamer_get_playing_track?;
+        // The streamer hands out a reference that we now own
+        let track = unsafe { get_track_fn() };
+        TrackRef::adopt(*self, track)
+    }
+
+    /// Takes a new reference to a track pointer received from DeaDBeeF.
+    ///
+    /// # Safety
+    ///
+    /// `track` must be null or point to a live playlist item.
+    pub unsafe fn track_ref(&self, track: *mut DB_playItem_t) -> Option<TrackRef> {
+        if track.is_null() {
+            return None;
+        }
+        let ref_fn = self.api.pl_item_ref?;
+        ref_fn(track);
+        TrackRef::adopt(*self, track)
+    }
+
+    /// Returns every metadata key and value set on `track`, in order.
+    ///
+    /// Keys starting with `:` are DeaDBeeF's internal properties, such as
+    /// `:URI` and `:DURATION`.
+    pub fn metadata(&self, track: &TrackRef) -> Result<Vec<(String, String)>, Error> {
+        let get_meta_fn = func(self.api.pl_get_metadata_head, "pl_get_metadata_head")?;
+
+        let _l = self.lock()?;
+        let mut entries = Vec::new();
+        let mut meta = unsafe { get_meta_fn(track.ptr).as_ref() };
+
+        while let Some(m) = meta {
+            // Tags are not guaranteed to be valid UTF-8
+            let (key, value) = unsafe {
+                (
+                    CStr::from_ptr(m.key).to_string_lossy().into_owned(),
+                    CStr::from_ptr(m.value).to_string_lossy().into_owned(),
+                )
+            };
+            entries.push((key, value));
+            meta = unsafe { m.next.as_ref() };
+        }
+
+        Ok(entries)
+    }
+
+    /// Returns the value of `key` on `track`, or `None` if it is unset.
+    pub fn meta(&self, track: &TrackRef, key: &str) -> Result<Option<String>, Error> {
+        let find_meta_fn = func(self.api.pl_find_meta, "pl_find_meta")?;
+        let key = CString::new(key).map_err(|_| Error::Failed("pl_find_meta"))?;
+
+        let _l = self.lock()?;
+        let value = unsafe { find_meta_fn(track.ptr, key.as_ptr()) };
+        if value.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(
+            unsafe { CStr::from_ptr(value) }
+                .to_string_lossy()
+                .into_owned(),
+        ))
+    }
+
+    /// Locks the playlists until the returned guard is dropped.
+    pub fn lock(&self) -> Result<PlaylistLock, Error> {
+        let lock_fn = func(self.api.pl_lock, "pl_lock")?;
+        let unlock_fn = func(self.api.pl_unlock, "pl_unlock")?;
+        unsafe { lock_fn() };
+        Ok(PlaylistLock { unlock_fn })
+    }
+
+    /// Reads a string value from the DeaDBeeF config, returning `default`
+    /// when the key is unset.
+    pub fn conf_str(&self, key: &str, default: &str) -> String {
+        let (conf_get_str_fn, key, default_c) = match (
+            self.api.conf_get_str,
+            CString::new(key),
+            CString::new(default),
+        ) {
+            (Some(f), Ok(k), Ok(d)) => (f, k, d),
+            _ => return default.to_string(),
+        };
+        let mut buf = [0 as c_char; 1024];
+
+        unsafe {
+            conf_get_str_fn(
+                key.as_ptr(),
+                default_c.as_ptr(),
+                buf.as_mut_ptr(),
+                buf.len() as i32,
+            );
+            CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+        }
+    }
+
+    /// Reads an integer value from the DeaDBeeF config, returning `default`
+    /// when the key is unset.
+    pub fn conf_int(&self, key: &str, default: i32) -> i32 {
+        match (self.api.conf_get_int, CString::new(key)) {
+            (Some(f), Ok(key)) => unsafe { f(key.as_ptr(), default) },
+            _ => default,
+        }
+    }
+
+    /// Writes an integer value to the DeaDBeeF config.
+    pub fn conf_set_int(&self, key: &str, value: i32) -> Result<(), Error> {
+        let conf_set_int_fn = func(self.api.conf_set_int, "conf_set_int")?;
+        let key = CString::new(key).map_err(|_| Error::Failed("conf_set_int"))?;
+        unsafe { conf_set_int_fn(key.as_ptr(), value) };
+        Ok(())
+    }
+
+    /// Writes `msg` to DeaDBeeF's log, returning `false` if the host has
+    /// no log API.
+    pub fn log(&self, msg: &str) -> bool {
+        if self.require(Feature::Log).is_err() {
+            return false;
+        }
+        let log_fn = match self.api.log {
+            Some(f) => f,
+            None => return false,
+        };
+        let msg = match CString::new(msg.replace('\0', "")) {
+            Ok(m) => m,
+            Err(_) => return false,
+        };
+        // Pass the message as an argument, so `%` in it is not interpreted
+        unsafe { log_fn(b"%s\0".as_ptr() as *const c_char, msg.as_ptr()) };
+        true
+    }
+}
+
+/// Guard holding the playlist lock, released on drop.
+pub struct PlaylistLock {
+    unlock_fn: unsafe extern "C" fn(),
+}
+
+impl Drop for PlaylistLock {
+    fn drop(&mut self) {
+        unsafe { (self.unlock_fn)() };
+    }
+}
+
+/// Counted reference to a playlist item, released on drop.
+pub struct TrackRef {
+    db: Deadbeef,
+    ptr: *mut DB_playItem_t,
+}
+
+impl TrackRef {
+    /// Wraps a pointer whose reference is already owned by the caller.
+    pub(super) fn adopt(db: Deadbeef, ptr: *mut DB_playItem_t) -> Option<Self> {
+        if ptr.is_null() {
+            None
+        } else {
+            Some(Self { db, ptr })
+        }
+    }
+
+    /// Identifier of the underlying item, stable for as long as it is loaded.
+    pub fn id(&self) -> usize {
+        self.ptr as usize
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut DB_playItem_t {
+        self.ptr
+    }
+}
+
+// DeaDBeeF reference counts items under its own lock, so references can
+// be taken and released from any thread.
+unsafe impl Send for TrackRef {}
+
+impl PartialEq for TrackRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl fmt::Debug for TrackRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("TrackRef").field(&self.ptr).finish()
+    }
+}
+
+impl Drop for TrackRef {
+    fn drop(&mut self) {
+        if let Some(unref_fn) = self.db.api.pl_item_unref {
+            unsafe { unref_fn(self.ptr) };
+        }
+    }
+}
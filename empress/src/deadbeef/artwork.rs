@@ -0,0 +1,129 @@
+//! Access to DeaDBeeF's artwork plugin, which finds covers in local files,
+//! embedded tags and online sources, and caches them.
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_int,
+    path::PathBuf,
+};
+
+use crate::{error, warn};
+
+use super::{
+    bindings::{ddb_artwork_plugin_t, ddb_cover_info_t, ddb_cover_query_t},
+    Deadbeef, TrackRef,
+};
+
+/// Plugin id of the artwork plugin since DeaDBeeF 1.9.
+const PLUGIN_ID: &str = "artwork2";
+
+/// Version of the artwork plugin API used here.
+const MAJOR_VERSION: i32 = 2;
+
+/// Called with the cover's file path once the lookup finishes.
+pub type CoverCallback = Box<dyn FnOnce(Option<PathBuf>) + Send>;
+
+/// Handle to a loaded artwork plugin.
+#[derive(Clone, Copy)]
+pub struct Artwork {
+    db: Deadbeef,
+    plugin: &'static ddb_artwork_plugin_t,
+}
+
+/// State carried through a cover query, reclaimed in [`cover_callback`].
+struct Request {
+    plugin: &'static ddb_artwork_plugin_t,
+    track: TrackRef,
+    done: CoverCallback,
+}
+
+impl Deadbeef {
+    /// Returns the artwork plugin, if it is loaded and speaks a known API.
+    pub fn artwork(&self) -> Option<Artwork> {
+        let plug_get_for_id_fn = self.api().plug_get_for_id?;
+        let id = CString::new(PLUGIN_ID).ok()?;
+
+        let plugin =
+            unsafe { (plug_get_for_id_fn(id.as_ptr()) as *const ddb_artwork_plugin_t).as_ref()? };
+
+        if plugin.plugin.plugin.version_major != MAJOR_VERSION {
+            warn!(
+                "unsupported artwork plugin version {}.{}",
+                plugin.plugin.plugin.version_major, plugin.plugin.plugin.version_minor
+            );
+            return None;
+        }
+
+        Some(Artwork { db: *self, plugin })
+    }
+}
+
+impl Artwork {
+    /// Requests the cover for `track`.
+    ///
+    /// The lookup runs asynchronously, and `done` is called on the artwork
+    /// plugin's thread. Returns `false` if the request could not be made,
+    /// in which case `done` is never called.
+    pub fn cover(&self, track: &TrackRef, done: CoverCallback) -> bool {
+        let cover_get_fn = match self.plugin.cover_get {
+            Some(f) => f,
+            None => return false,
+        };
+        let track_ptr = track.as_ptr();
+        let track = match unsafe { self.db.track_ref(track_ptr) } {
+            Some(t) => t,
+            None => return false,
+        };
+
+        let request = Box::new(Request {
+            plugin: self.plugin,
+            track,
+            done,
+        });
+
+        let mut query: ddb_cover_query_t = unsafe { std::mem::zeroed() };
+        query._size = std::mem::size_of::<ddb_cover_query_t>() as u32;
+        query.track = track_ptr;
+        query.user_data = Box::into_raw(request).cast();
+
+        unsafe { cover_get_fn(Box::into_raw(Box::new(query)), Some(cover_callback)) };
+        true
+    }
+}
+
+/// Completion callback for `cover_get`, which owns the query from here on.
+unsafe extern "C" fn cover_callback(
+    error: c_int,
+    query: *mut ddb_cover_query_t,
+    cover: *mut ddb_cover_info_t,
+) {
+    if query.is_null() {
+        return;
+    }
+    let query = Box::from_raw(query);
+    let request = Box::from_raw(query.user_data as *mut Request);
+
+    let path = match cover.as_ref() {
+        Some(info) if error == 0 && info.cover_found != 0 && !info.image_filename.is_null() => {
+            CStr::from_ptr(info.image_filename)
+                .to_str()
+                .ok()
+                .map(PathBuf::from)
+        }
+        _ => None,
+    };
+
+    if !cover.is_null() {
+        if let Some(release_fn) = request.plugin.cover_info_release {
+            release_fn(cover);
+        }
+    }
+
+    let Request { track, done, .. } = *request;
+    // Release the track before handing over, the callback may be slow
+    drop(track);
+
+    // A panic here would unwind into the artwork plugin
+    if std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| done(path))).is_err() {
+        error!("panic while handling album art from the artwork plugin");
+    }
+}
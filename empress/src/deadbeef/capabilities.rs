@@ -0,0 +1,168 @@
+//! What the running DeaDBeeF supports, decided from the API version it
+//! reports.
+//!
+//! `DB_functions_t` only grows, so a host built against an older API has a
+//! shorter table than the bindings. Fields added after the host's version
+//! must not be read at all, not merely checked for null.
+//!
+//! DeaDBeeF refuses to load a plugin asking for a newer API than its own,
+//! so the plugin asks for [`ApiVersion::MIN`], which brought the actions'
+//! `callback2`, and uses the functions up to it freely. Everything newer is
+//! a [`Feature`], checked before its fields are read, so older hosts still
+//! load the plugin without it.
+use std::fmt;
+
+use super::bindings::{DB_functions_t, DB_API_VERSION_MAJOR, DB_API_VERSION_MINOR};
+
+/// Version of the plugin API, as `major.minor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ApiVersion {
+    pub major: i32,
+    pub minor: i32,
+}
+
+impl ApiVersion {
+    /// Oldest API the plugin asks for, and so the oldest host that loads it.
+    pub const MIN: ApiVersion = ApiVersion::new(1, 5);
+
+    /// API of the headers the bindings were generated from.
+    pub const BUILT: ApiVersion =
+        ApiVersion::new(DB_API_VERSION_MAJOR as i32, DB_API_VERSION_MINOR as i32);
+
+    pub const fn new(major: i32, minor: i32) -> Self {
+        Self { major, minor }
+    }
+
+    /// The version reported by the host in its API table.
+    pub fn of(api: &DB_functions_t) -> Self {
+        Self::new(api.vmajor, api.vminor)
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+/// Optional features and the API version that introduced them.
+const FEATURES: &[(Feature, ApiVersion)] = &[
+    (Feature::TitleFormat, ApiVersion::new(1, 8)),
+    (Feature::PlayQueue, ApiVersion::new(1, 9)),
+    (Feature::Shuffle, ApiVersion::new(1, 10)),
+    (Feature::Log, ApiVersion::new(1, 12)),
+];
+
+/// Optional host features used by the plugin, newer than [`ApiVersion::MIN`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    /// Title-format scripts (`tf_compile`, `tf_eval`, `tf_free`)
+    TitleFormat,
+    /// Reading and editing the play queue (`playqueue_*`)
+    PlayQueue,
+    /// Reading and setting the shuffle mode (`streamer_get_shuffle`,
+    /// `streamer_set_shuffle`)
+    Shuffle,
+    /// Writing to DeaDBeeF's log window (`log`)
+    Log,
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Feature::TitleFormat => "title formats",
+            Feature::PlayQueue => "play queue",
+            Feature::Shuffle => "shuffle",
+            Feature::Log => "log",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The features available from a particular host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    version: ApiVersion,
+}
+
+impl Capabilities {
+    pub fn new(version: ApiVersion) -> Self {
+        Self { version }
+    }
+
+    pub fn version(&self) -> ApiVersion {
+        self.version
+    }
+
+    /// Whether the host's API table has the functions `feature` needs.
+    ///
+    /// A host with a different major version has an incompatible table,
+    /// so nothing optional is used with it.
+    pub fn has(&self, feature: Feature) -> bool {
+        FEATURES
+            .iter()
+            .find(|(f, _)| *f == feature)
+            .map_or(false, |(_, since)| {
+                self.version.major == since.major && self.version >= *since
+            })
+    }
+}
+
+impl fmt::Display for Capabilities {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "API {}", self.version)?;
+        for (feature, since) in FEATURES {
+            if self.has(*feature) {
+                write!(f, ", {}", feature)?;
+            } else {
+                write!(f, ", no {} (needs {})", feature, since)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn features_follow_the_host_version() {
+        let old = Capabilities::new(ApiVersion::MIN);
+        assert!(!old.has(Feature::TitleFormat) && !old.has(Feature::PlayQueue));
+        assert_eq!(
+            old.to_string(),
+            "API 1.5, no title formats (needs 1.8), no play queue (needs 1.9), \
+             no shuffle (needs 1.10), no log (needs 1.12)"
+        );
+
+        let mid = Capabilities::new(ApiVersion::new(1, 9));
+        assert!(mid.has(Feature::TitleFormat) && mid.has(Feature::PlayQueue));
+        assert!(!mid.has(Feature::Shuffle) && !mid.has(Feature::Log));
+        assert_eq!(
+            mid.to_string(),
+            "API 1.9, title formats, play queue, no shuffle (needs 1.10), no log (needs 1.12)"
+        );
+
+        let new = Capabilities::new(ApiVersion::new(1, 17));
+        for (feature, _) in FEATURES {
+            assert!(new.has(*feature), "{} missing", feature);
+        }
+        assert_eq!(
+            new.to_string(),
+            "API 1.17, title formats, play queue, shuffle, log"
+        );
+
+        let other = Capabilities::new(ApiVersion::new(2, 0));
+        for (feature, _) in FEATURES {
+            assert!(!other.has(*feature), "{} used", feature);
+        }
+    }
+
+    #[test]
+    fn features_are_newer_than_the_minimum() {
+        for (feature, since) in FEATURES {
+            assert!(*since > ApiVersion::MIN, "{} needs no check", feature);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! Typed decoding of the messages DeaDBeeF sends to plugins.
+use super::{
+    bindings::{
+        self, ddb_event_playpos_t, ddb_event_t, ddb_event_track_t, ddb_event_trackchange_t,
+    },
+    Deadbeef, TrackRef,
+};
+
+/// Kind of change reported by `DB_EV_PLAYLISTCHANGED`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaylistChange {
+    Content,
+    Created,
+    Deleted,
+    Position,
+    Title,
+    Selection,
+    SearchResult,
+    PlayQueue,
+    Other(u32),
+}
+
+impl From<u32> for PlaylistChange {
+    fn from(kind: u32) -> Self {
+        match kind {
+            bindings::DDB_PLAYLIST_CHANGE_CONTENT => PlaylistChange::Content,
+            bindings::DDB_PLAYLIST_CHANGE_CREATED => PlaylistChange::Created,
+            bindings::DDB_PLAYLIST_CHANGE_DELETED => PlaylistChange::Deleted,
+            bindings::DDB_PLAYLIST_CHANGE_POSITION => PlaylistChange::Position,
+            bindings::DDB_PLAYLIST_CHANGE_TITLE => PlaylistChange::Title,
+            bindings::DDB_PLAYLIST_CHANGE_SELECTION => PlaylistChange::Selection,
+            bindings::DDB_PLAYLIST_CHANGE_SEARCHRESULT => PlaylistChange::SearchResult,
+            bindings::DDB_PLAYLIST_CHANGE_PLAYQUEUE => PlaylistChange::PlayQueue,
+            other => PlaylistChange::Other(other),
+        }
+    }
+}
+
+/// A message received through the plugin's `message` callback.
+///
+/// https://github.com/DeaDBeeF-Player/deadbeef/blob/master/include/deadbeef/deadbeef.h
+#[derive(Debug, PartialEq)]
+pub enum DeadbeefEvent {
+    Next,
+    Prev,
+    PlayCurrent,
+    /// Play the track at index `p1` of the current playlist
+    PlayNum(u32),
+    Stop,
+    Pause,
+    PlayRandom,
+    Terminate,
+    PlaylistRefresh,
+    ReinitSound,
+    ConfigChanged,
+    TogglePause,
+    Activated,
+    Paused(bool),
+    PlaylistChanged(PlaylistChange),
+    VolumeChanged,
+    OutputChanged,
+    PlaylistSwitched,
+    /// Seek the current track to the position, in milliseconds
+    Seek(u32),
+    ActionsChanged,
+    DspChainChanged,
+    SelectionChanged,
+    PluginsLoaded,
+    FocusSelection,
+    PlaybackStateDidChange,
+    PlayNextAlbum,
+    PlayPrevAlbum,
+    PlayRandomAlbum,
+    SongChanged {
+        from: Option<TrackRef>,
+        to: Option<TrackRef>,
+    },
+    SongStarted(Option<TrackRef>),
+    SongFinished(Option<TrackRef>),
+    TrackInfoChanged(Option<TrackRef>),
+    /// The track was seeked to `position`, in seconds
+    Seeked {
+        track: Option<TrackRef>,
+        position: f32,
+    },
+    CursorMoved(Option<TrackRef>),
+    Unknown {
+        id: u32,
+        ctx: usize,
+        p1: u32,
+        p2: u32,
+    },
+}
+
+impl DeadbeefEvent {
+    /// Decodes a raw `(id, ctx, p1, p2)` message.
+    ///
+    /// Tracks referenced by structured events gain a reference that is
+    /// released when the event is dropped.
+    ///
+    /// # Safety
+    ///
+    /// `ctx` must be the value DeaDBeeF passed alongside `id`: either zero,
+    /// or a pointer to the event struct that `id` documents.
+    pub unsafe fn decode(db: &Deadbeef, id: u32, ctx: usize, p1: u32, p2: u32) -> Self {
+        let unknown = DeadbeefEvent::Unknown { id, ctx, p1, p2 };
+
+        match id {
+            bindings::DB_EV_NEXT => DeadbeefEvent::Next,
+            bindings::DB_EV_PREV => DeadbeefEvent::Prev,
+            bindings::DB_EV_PLAY_CURRENT => DeadbeefEvent::PlayCurrent,
+            bindings::DB_EV_PLAY_NUM => DeadbeefEvent::PlayNum(p1),
+            bindings::DB_EV_STOP => DeadbeefEvent::Stop,
+            bindings::DB_EV_PAUSE => DeadbeefEvent::Pause,
+            bindings::DB_EV_PLAY_RANDOM => DeadbeefEvent::PlayRandom,
+            bindings::DB_EV_TERMINATE => DeadbeefEvent::Terminate,
+            bindings::DB_EV_PLAYLIST_REFRESH => DeadbeefEvent::PlaylistRefresh,
+            bindings::DB_EV_REINIT_SOUND => DeadbeefEvent::ReinitSound,
+            bindings::DB_EV_CONFIGCHANGED => DeadbeefEvent::ConfigChanged,
+            bindings::DB_EV_TOGGLE_PAUSE => DeadbeefEvent::TogglePause,
+            bindings::DB_EV_ACTIVATED => DeadbeefEvent::Activated,
+            bindings::DB_EV_PAUSED => DeadbeefEvent::Paused(p1 > 0),
+            bindings::DB_EV_PLAYLISTCHANGED => DeadbeefEvent::PlaylistChanged(p1.into()),
+            bindings::DB_EV_VOLUMECHANGED => DeadbeefEvent::VolumeChanged,
+            bindings::DB_EV_OUTPUTCHANGED => DeadbeefEvent::OutputChanged,
+            bindings::DB_EV_PLAYLISTSWITCHED => DeadbeefEvent::PlaylistSwitched,
+            bindings::DB_EV_SEEK => DeadbeefEvent::Seek(p1),
+            bindings::DB_EV_ACTIONSCHANGED => DeadbeefEvent::ActionsChanged,
+            bindings::DB_EV_DSPCHAINCHANGED => DeadbeefEvent::DspChainChanged,
+            bindings::DB_EV_SELCHANGED => DeadbeefEvent::SelectionChanged,
+            bindings::DB_EV_PLUGINSLOADED => DeadbeefEvent::PluginsLoaded,
+            bindings::DB_EV_FOCUS_SELECTION => DeadbeefEvent::FocusSelection,
+            bindings::DB_EV_PLAYBACK_STATE_DID_CHANGE => DeadbeefEvent::PlaybackStateDidChange,
+            bindings::DB_EV_PLAY_NEXT_ALBUM => DeadbeefEvent::PlayNextAlbum,
+            bindings::DB_EV_PLAY_PREV_ALBUM => DeadbeefEvent::PlayPrevAlbum,
+            bindings::DB_EV_PLAY_RANDOM_ALBUM => DeadbeefEvent::PlayRandomAlbum,
+            // DB_EV_FIRST == DB_EV_SONGCHANGED
+            bindings::DB_EV_SONGCHANGED => match payload::<ddb_event_trackchange_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::SongChanged {
+                    from: db.track_ref(ev.from),
+                    to: db.track_ref(ev.to),
+                },
+                None => unknown,
+            },
+            bindings::DB_EV_SONGSTARTED => match payload::<ddb_event_track_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::SongStarted(db.track_ref(ev.track)),
+                None => unknown,
+            },
+            bindings::DB_EV_SONGFINISHED => match payload::<ddb_event_track_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::SongFinished(db.track_ref(ev.track)),
+                None => unknown,
+            },
+            bindings::DB_EV_TRACKINFOCHANGED => match payload::<ddb_event_track_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::TrackInfoChanged(db.track_ref(ev.track)),
+                None => unknown,
+            },
+            bindings::DB_EV_SEEKED => match payload::<ddb_event_playpos_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::Seeked {
+                    track: db.track_ref(ev.track),
+                    position: ev.playpos,
+                },
+                None => unknown,
+            },
+            bindings::DB_EV_CURSOR_MOVED => match payload::<ddb_event_track_t>(id, ctx) {
+                Some(ev) => DeadbeefEvent::CursorMoved(db.track_ref(ev.track)),
+                None => unknown,
+            },
+            _ => unknown,
+        }
+    }
+}
+
+/// Reads the event struct `T` behind `ctx`, rejecting null pointers and
+/// payloads whose header does not match the message `id`.
+unsafe fn payload<'a, T>(id: u32, ctx: usize) -> Option<&'a T> {
+    let header = (ctx as *const ddb_event_t).as_ref()?;
+    if header.event as u32 != id {
+        return None;
+    }
+    (ctx as *const T).as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, mem, ptr};
+
+    use super::*;
+    use crate::deadbeef::bindings::{DB_functions_t, DB_playItem_t};
+
+    thread_local! {
+        /// References held on fake items by the current test
+        static REFS: Cell<i32> = Cell::new(0);
+    }
+
+    unsafe extern "C" fn item_ref(_: *mut DB_playItem_t) {
+        REFS.with(|refs| refs.set(refs.get() + 1));
+    }
+
+    unsafe extern "C" fn item_unref(_: *mut DB_playItem_t) {
+        REFS.with(|refs| refs.set(refs.get() - 1));
+    }
+
+    fn refs() -> i32 {
+        REFS.with(Cell::get)
+    }
+
+    /// An API table with only the reference counting functions.
+    fn deadbeef() -> Deadbeef {
+        let mut api: DB_functions_t = unsafe { mem::zeroed() };
+        api.pl_item_ref = Some(item_ref);
+        api.pl_item_unref = Some(item_unref);
+        Deadbeef::new(Box::leak(Box::new(api)))
+    }
+
+    fn item() -> Box<DB_playItem_t> {
+        Box::new(unsafe { mem::zeroed() })
+    }
+
+    fn header(id: u32) -> ddb_event_t {
+        let mut header: ddb_event_t = unsafe { mem::zeroed() };
+        header.event = id as _;
+        header
+    }
+
+    fn track_event(id: u32, track: *mut DB_playItem_t) -> ddb_event_track_t {
+        let mut ev: ddb_event_track_t = unsafe { mem::zeroed() };
+        ev.ev = header(id);
+        ev.track = track;
+        ev
+    }
+
+    unsafe fn decode<T>(db: &Deadbeef, id: u32, ev: &T, p1: u32) -> DeadbeefEvent {
+        DeadbeefEvent::decode(db, id, ev as *const T as usize, p1, 0)
+    }
+
+    #[test]
+    fn simple_messages_use_their_arguments() {
+        let db = deadbeef();
+        let decoded = |id, p1| unsafe { DeadbeefEvent::decode(&db, id, 0, p1, 0) };
+
+        assert_eq!(decoded(bindings::DB_EV_NEXT, 0), DeadbeefEvent::Next);
+        assert_eq!(
+            decoded(bindings::DB_EV_PAUSED, 1),
+            DeadbeefEvent::Paused(true)
+        );
+        assert_eq!(
+            decoded(bindings::DB_EV_PAUSED, 0),
+            DeadbeefEvent::Paused(false)
+        );
+        assert_eq!(
+            decoded(bindings::DB_EV_SEEK, 1500),
+            DeadbeefEvent::Seek(1500)
+        );
+        assert_eq!(
+            decoded(
+                bindings::DB_EV_PLAYLISTCHANGED,
+                bindings::DDB_PLAYLIST_CHANGE_PLAYQUEUE
+            ),
+            DeadbeefEvent::PlaylistChanged(PlaylistChange::PlayQueue)
+        );
+        assert_eq!(
+            decoded(bindings::DB_EV_PLAYLISTCHANGED, 99),
+            DeadbeefEvent::PlaylistChanged(PlaylistChange::Other(99))
+        );
+    }
+
+    #[test]
+    fn track_events_hold_a_reference() {
+        let db = deadbeef();
+        let mut from = item();
+        let from_ptr: *mut DB_playItem_t = &mut *from;
+
+        let mut ev: ddb_event_trackchange_t = unsafe { mem::zeroed() };
+        ev.ev = header(bindings::DB_EV_SONGCHANGED);
+        ev.from = from_ptr;
+        ev.to = ptr::null_mut();
+
+        let decoded = unsafe { decode(&db, bindings::DB_EV_SONGCHANGED, &ev, 0) };
+        match &decoded {
+            DeadbeefEvent::SongChanged {
+                from: Some(from),
+                to: None,
+            } => assert_eq!(from.id(), from_ptr as usize),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(refs(), 1);
+
+        drop(decoded);
+        assert_eq!(refs(), 0);
+    }
+
+    #[test]
+    fn seeked_carries_the_position() {
+        let db = deadbeef();
+        let mut track = item();
+
+        let mut ev: ddb_event_playpos_t = unsafe { mem::zeroed() };
+        ev.ev = header(bindings::DB_EV_SEEKED);
+        ev.track = &mut *track;
+        ev.playpos = 42.5;
+
+        match unsafe { decode(&db, bindings::DB_EV_SEEKED, &ev, 0) } {
+            DeadbeefEvent::Seeked {
+                track: Some(_),
+                position,
+            } => assert_eq!(position, 42.5),
+            other => panic!("unexpected event: {:?}", other),
+        }
+        assert_eq!(refs(), 0);
+    }
+
+    #[test]
+    fn mismatched_payloads_are_unknown() {
+        let db = deadbeef();
+        let mut track = item();
+        // A track event whose header names a different message
+        let ev = track_event(bindings::DB_EV_SONGFINISHED, &mut *track);
+        let ctx = &ev as *const ddb_event_track_t as usize;
+
+        let decoded = unsafe { decode(&db, bindings::DB_EV_SONGSTARTED, &ev, 3) };
+        assert_eq!(
+            decoded,
+            DeadbeefEvent::Unknown {
+                id: bindings::DB_EV_SONGSTARTED,
+                ctx,
+                p1: 3,
+                p2: 0,
+            }
+        );
+        assert_eq!(refs(), 0);
+
+        let matched = unsafe { decode(&db, bindings::DB_EV_SONGFINISHED, &ev, 0) };
+        assert!(matches!(matched, DeadbeefEvent::SongFinished(Some(_))));
+    }
+
+    #[test]
+    fn null_payloads_are_unknown() {
+        let db = deadbeef();
+        for id in [
+            bindings::DB_EV_SONGCHANGED,
+            bindings::DB_EV_SONGSTARTED,
+            bindings::DB_EV_TRACKINFOCHANGED,
+            bindings::DB_EV_SEEKED,
+        ] {
+            let decoded = unsafe { DeadbeefEvent::decode(&db, id, 0, 0, 0) };
+            assert_eq!(
+                decoded,
+                DeadbeefEvent::Unknown {
+                    id,
+                    ctx: 0,
+                    p1: 0,
+                    p2: 0,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn null_tracks_are_none() {
+        let db = deadbeef();
+        let ev = track_event(bindings::DB_EV_TRACKINFOCHANGED, ptr::null_mut());
+
+        let decoded = unsafe { decode(&db, bindings::DB_EV_TRACKINFOCHANGED, &ev, 0) };
+        assert_eq!(decoded, DeadbeefEvent::TrackInfoChanged(None));
+        assert_eq!(refs(), 0);
+    }
+
+    #[test]
+    fn unhandled_messages_are_unknown() {
+        let db = deadbeef();
+        let decoded = unsafe { DeadbeefEvent::decode(&db, 12345, 7, 1, 2) };
+        assert_eq!(
+            decoded,
+            DeadbeefEvent::Unknown {
+                id: 12345,
+                ctx: 7,
+                p1: 1,
+                p2: 2,
+            }
+        );
+    }
+}
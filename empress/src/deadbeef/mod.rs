@@ -1,8 +1,15 @@
-#![allow(
-    non_upper_case_globals,
-    non_camel_case_types,
-    non_snake_case,
-    dead_code,
-    clippy::all
-)]
-include!(concat!(env!("OUT_DIR"), "/deadbeef.rs"));
+mod api;
+mod artwork;
+mod bindings;
+mod capabilities;
+mod event;
+mod playqueue;
+mod tags;
+mod title_format;
+
+pub use api::{Command, Deadbeef, Error, OutputState, PlaylistLock, TrackRef};
+pub use artwork::{Artwork, CoverCallback};
+pub use bindings::*;
+pub use capabilities::{ApiVersion, Capabilities, Feature};
+pub use event::{DeadbeefEvent, PlaylistChange};
+pub use title_format::TitleFormat;
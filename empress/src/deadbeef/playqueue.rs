@@ -0,0 +1,92 @@
+//! The play queue, holding tracks to play before the playlist continues,
+//! and lookup of items across every playlist.
+use std::os::raw::c_int;
+
+use super::{
+    api::func,
+    bindings::{ddb_playlist_t, PL_MAIN},
+    Deadbeef, Error, Feature, TrackRef,
+};
+
+/// Counted reference to a playlist, released on drop.
+struct PlaylistRef {
+    db: Deadbeef,
+    ptr: *mut ddb_playlist_t,
+}
+
+impl Drop for PlaylistRef {
+    fn drop(&mut self) {
+        if let Some(unref_fn) = self.db.api().plt_unref {
+            unsafe { unref_fn(self.ptr) };
+        }
+    }
+}
+
+impl Deadbeef {
+    /// Returns the queued tracks, in the order they will play.
+    pub fn playqueue(&self) -> Result<Vec<TrackRef>, Error> {
+        self.require(Feature::PlayQueue)?;
+        let count_fn = func(self.api().playqueue_get_count, "playqueue_get_count")?;
+        let get_item_fn = func(self.api().playqueue_get_item, "playqueue_get_item")?;
+
+        let _l = self.lock()?;
+        let count = unsafe { count_fn() };
+        // Each item comes with a reference that we now own
+        Ok((0..count)
+            .filter_map(|n| TrackRef::adopt(*self, unsafe { get_item_fn(n) }))
+            .collect())
+    }
+
+    /// Adds `track` to the end of the play queue.
+    pub fn playqueue_push(&self, track: &TrackRef) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        let push_fn = func(self.api().playqueue_push, "playqueue_push")?;
+        if unsafe { push_fn(track.as_ptr()) } < 0 {
+            return Err(Error::Failed("playqueue_push"));
+        }
+        Ok(())
+    }
+
+    /// Removes every occurrence of `track` from the play queue.
+    pub fn playqueue_remove(&self, track: &TrackRef) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        let remove_fn = func(self.api().playqueue_remove, "playqueue_remove")?;
+        unsafe { remove_fn(track.as_ptr()) };
+        Ok(())
+    }
+
+    pub fn playqueue_clear(&self) -> Result<(), Error> {
+        self.require(Feature::PlayQueue)?;
+        let clear_fn = func(self.api().playqueue_clear, "playqueue_clear")?;
+        unsafe { clear_fn() };
+        Ok(())
+    }
+
+    /// Returns the first item, in any playlist, for which `matches` is true.
+    pub fn find_track(
+        &self,
+        mut matches: impl FnMut(&TrackRef) -> bool,
+    ) -> Result<Option<TrackRef>, Error> {
+        let count_fn = func(self.api().plt_get_count, "plt_get_count")?;
+        let get_plt_fn = func(self.api().plt_get_for_idx, "plt_get_for_idx")?;
+        let first_fn = func(self.api().plt_get_first, "plt_get_first")?;
+        let next_fn = func(self.api().pl_get_next, "pl_get_next")?;
+
+        let _l = self.lock()?;
+        for idx in 0..unsafe { count_fn() } {
+            let plt = match unsafe { get_plt_fn(idx) } {
+                ptr if ptr.is_null() => continue,
+                ptr => PlaylistRef { db: *self, ptr },
+            };
+
+            let mut item = TrackRef::adopt(*self, unsafe { first_fn(plt.ptr, PL_MAIN as c_int) });
+            while let Some(track) = item {
+                if matches(&track) {
+                    return Ok(Some(track));
+                }
+                item = TrackRef::adopt(*self, unsafe { next_fn(track.as_ptr(), PL_MAIN as c_int) });
+            }
+        }
+        Ok(None)
+    }
+}
@@ -0,0 +1,68 @@
+//! Editing tags and saving them to the track's file.
+use std::ffi::{CStr, CString};
+
+use super::{
+    api::func,
+    bindings::{ddb_event_t, ddb_event_track_t, DB_EV_TRACKINFOCHANGED},
+    Deadbeef, Error, TrackRef,
+};
+
+impl Deadbeef {
+    /// Sets `key` on `track` to `value`, replacing any previous value.
+    pub fn set_meta(&self, track: &TrackRef, key: &str, value: &str) -> Result<(), Error> {
+        let replace_fn = func(self.api().pl_replace_meta, "pl_replace_meta")?;
+        let key = CString::new(key).map_err(|_| Error::Failed("pl_replace_meta"))?;
+        let value = CString::new(value).map_err(|_| Error::Failed("pl_replace_meta"))?;
+        unsafe { replace_fn(track.as_ptr(), key.as_ptr(), value.as_ptr()) };
+        Ok(())
+    }
+
+    /// Saves the tags of `track` to its file, through the decoder that
+    /// plays it, as DeaDBeeF's tag editor does.
+    pub fn write_tags(&self, track: &TrackRef) -> Result<(), Error> {
+        let list_fn = func(self.api().plug_get_decoder_list, "plug_get_decoder_list")?;
+        // Streams and other items without a decoder have no file to write
+        let decoder_id = self
+            .meta(track, ":DECODER")?
+            .ok_or(Error::ReadOnly(track.id()))?;
+
+        let mut decoder = unsafe { list_fn() };
+        if decoder.is_null() {
+            return Err(Error::Null("plug_get_decoder_list"));
+        }
+        // The list is terminated by a null pointer
+        while let Some(dec) = unsafe { (*decoder).as_ref() } {
+            let id = unsafe { CStr::from_ptr(dec.plugin.id) };
+            if id.to_bytes() == decoder_id.as_bytes() {
+                let write_fn = dec.write_metadata.ok_or(Error::ReadOnly(track.id()))?;
+                if unsafe { write_fn(track.as_ptr()) } != 0 {
+                    return Err(Error::Failed("write_metadata"));
+                }
+                return Ok(());
+            }
+            decoder = unsafe { decoder.add(1) };
+        }
+        Err(Error::ReadOnly(track.id()))
+    }
+
+    /// Tells every plugin, including this one, that the tags of `track`
+    /// changed.
+    pub fn track_info_changed(&self, track: &TrackRef) -> Result<(), Error> {
+        let alloc_fn = func(self.api().event_alloc, "event_alloc")?;
+        let send_fn = func(self.api().event_send, "event_send")?;
+        let ref_fn = func(self.api().pl_item_ref, "pl_item_ref")?;
+
+        let ev = unsafe { alloc_fn(DB_EV_TRACKINFOCHANGED) } as *mut ddb_event_track_t;
+        if ev.is_null() {
+            return Err(Error::Null("event_alloc"));
+        }
+        // The event owns a reference, which DeaDBeeF releases once every
+        // plugin has seen it
+        unsafe {
+            ref_fn(track.as_ptr());
+            (*ev).track = track.as_ptr();
+            send_fn(ev as *mut ddb_event_t, 0, 0);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! DeaDBeeF's title formatting engine, which renders scripts such as
+//! `$if(%title%,%title%,%filename%)` for a track.
+use std::{
+    ffi::{CStr, CString},
+    os::raw::c_char,
+};
+
+use super::{api::func, bindings::ddb_tf_context_t, Deadbeef, Error, Feature, TrackRef};
+
+/// Longest output kept from a script, in bytes.
+const MAX_OUTPUT: usize = 1024;
+
+/// A compiled title-format script, freed on drop.
+pub struct TitleFormat {
+    db: Deadbeef,
+    code: *mut c_char,
+}
+
+// The bytecode is a private buffer that DeaDBeeF only reads while
+// evaluating, so it can move between threads.
+unsafe impl Send for TitleFormat {}
+
+impl Deadbeef {
+    /// Compiles `script`, returning `None` if it is not valid.
+    pub fn tf_compile(&self, script: &str) -> Result<Option<TitleFormat>, Error> {
+        self.require(Feature::TitleFormat)?;
+        let compile_fn = func(self.api().tf_compile, "tf_compile")?;
+        let script = match CString::new(script) {
+            Ok(s) => s,
+            Err(_) => return Ok(None),
+        };
+
+        let code = unsafe { compile_fn(script.as_ptr()) };
+        if code.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(TitleFormat { db: *self, code }))
+        }
+    }
+}
+
+impl TitleFormat {
+    /// Evaluates the script for `track`.
+    pub fn eval(&self, track: &TrackRef) -> Result<String, Error> {
+        let eval_fn = func(self.db.api().tf_eval, "tf_eval")?;
+
+        let mut ctx: ddb_tf_context_t = unsafe { std::mem::zeroed() };
+        ctx._size = std::mem::size_of::<ddb_tf_context_t>() as _;
+        ctx.it = track.as_ptr();
+        ctx.idx = -1;
+        ctx.id = -1;
+
+        let mut out = vec![0u8; MAX_OUTPUT];
+        let len = unsafe {
+            eval_fn(
+                &mut ctx,
+                self.code,
+                out.as_mut_ptr() as *mut c_char,
+                out.len() as _,
+            )
+        };
+        if len < 0 {
+            return Err(Error::Failed("tf_eval"));
+        }
+
+        let text = CStr::from_bytes_until_nul(&out)
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(text)
+    }
+}
+
+impl Drop for TitleFormat {
+    fn drop(&mut self) {
+        if let Some(free_fn) = self.db.api().tf_free {
+            unsafe { free_fn(self.code) };
+        }
+    }
+}
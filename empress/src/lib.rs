@@ -1,3 +1,10 @@
 #![deny(clippy::all)]
+#[cfg_attr(not(feature = "art"), path = "art/disabled.rs")]
+mod art;
+pub mod backend;
 pub mod deadbeef;
+pub mod log;
 pub mod mpris;
+pub mod rating;
+pub mod settings;
+pub mod uri;
@@ -0,0 +1,154 @@
+//! Leveled logging, written to DeaDBeeF's log when the host provides one
+//! and to stderr otherwise.
+//!
+//! Nothing is logged until [`set_level`] raises the level from
+//! [`Level::Off`].
+use std::{
+    fmt,
+    sync::{
+        atomic::{AtomicU8, Ordering},
+        Mutex,
+    },
+};
+
+use crate::deadbeef::Deadbeef;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Level {
+    #[default]
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    const ALL: [Level; 6] = [
+        Level::Off,
+        Level::Error,
+        Level::Warn,
+        Level::Info,
+        Level::Debug,
+        Level::Trace,
+    ];
+
+    /// Converts the index stored by the config dialog's level selector.
+    pub fn from_index(index: i32) -> Option<Self> {
+        Self::ALL.get(usize::try_from(index).ok()?).copied()
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Level::Off => "off",
+            Level::Error => "error",
+            Level::Warn => "warning",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        };
+        f.write_str(name)
+    }
+}
+
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Off as u8);
+
+/// DeaDBeeF handle used to reach its log.
+struct Sink(Deadbeef);
+
+// The API table is immutable and DeaDBeeF's log is safe to call from any
+// thread.
+unsafe impl Send for Sink {}
+
+static SINK: Mutex<Option<Sink>> = Mutex::new(None);
+
+/// Sets the most verbose level that is written.
+pub fn set_level(level: Level) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn level() -> Level {
+    Level::ALL[LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+/// Whether messages at `level` are currently written.
+pub fn enabled(level: Level) -> bool {
+    level != Level::Off && level <= self::level()
+}
+
+/// Sends messages to DeaDBeeF's log, or to stderr when `db` is `None`.
+pub fn set_sink(db: Option<Deadbeef>) {
+    *SINK.lock().unwrap_or_else(|e| e.into_inner()) = db.map(Sink);
+}
+
+/// Writes a message at `level`. Use the level macros instead, which skip
+/// formatting when the level is disabled.
+#[doc(hidden)]
+pub fn write(level: Level, args: fmt::Arguments<'_>) {
+    let line = format!("mpris: {}: {}\n", level, args);
+
+    let logged = match SINK.lock().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        Some(Sink(db)) => db.log(&line),
+        None => false,
+    };
+    if !logged {
+        eprint!("{}", line);
+    }
+}
+
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $($arg:tt)+) => {
+        if $crate::log::enabled($level) {
+            $crate::log::write($level, format_args!($($arg)+))
+        }
+    };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Error, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Warn, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Info, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Debug, $($arg)+) };
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($arg:tt)+) => { $crate::log!($crate::log::Level::Trace, $($arg)+) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_filter_less_severe_messages() {
+        assert_eq!(level(), Level::Off);
+        assert!(!enabled(Level::Error));
+
+        set_level(Level::from_index(3).unwrap());
+        assert_eq!(level(), Level::Info);
+        assert!(enabled(Level::Error) && enabled(Level::Warn) && enabled(Level::Info));
+        assert!(!enabled(Level::Debug) && !enabled(Level::Off));
+
+        assert_eq!(Level::from_index(6), None);
+        assert_eq!(Level::from_index(-1), None);
+        set_level(Level::Off);
+    }
+}
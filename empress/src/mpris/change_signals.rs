@@ -1,292 +1,264 @@
+#[cfg(feature = "player-extension")]
+use std::cell::RefCell;
 use std::rc::Rc;
 
-use crate::{deadbeef, mpris::player::Lock};
+use crate::{
+    art::ArtFinder,
+    backend::{Backend, Track},
+    deadbeef::DeadbeefEvent,
+    debug, trace, warn,
+};
 use dbus::{
     arg::{Array, PropMap, Variant},
     blocking::LocalConnection,
     channel::Sender,
     strings::Interface,
-    Path,
+    Message, Path,
 };
 use dbus_tree::Signal;
 
+#[cfg(feature = "notifications")]
+use super::notifications::Notifier;
+#[cfg(feature = "player-extension")]
+use super::player_extension;
+use super::{
+    inhibit::Inhibitor,
+    metadata::{can_seek, track_metadata},
+};
+#[cfg(feature = "player-extension")]
+use crate::backend::Extras;
+#[cfg(feature = "queue")]
+use crate::deadbeef::{Feature, PlaylistChange};
+
+/// Announces DeaDBeeF's events on the bus.
+///
+/// Only used on the listener thread, which owns the connection, the album
+/// art caches and the inhibitor and notifier state.
 pub(super) struct SigHandler {
     conn: Rc<LocalConnection>,
     sig: Signal<()>,
-    api: &'static deadbeef::DB_functions_t,
+    db: Rc<dyn Backend>,
+    art: Rc<ArtFinder>,
+    inhibitor: Inhibitor,
+    #[cfg(feature = "notifications")]
+    notifier: Option<Notifier>,
+    /// Playback options last announced
+    #[cfg(feature = "player-extension")]
+    extras: RefCell<Option<Extras>>,
 }
 
 impl SigHandler {
     pub fn new(
         conn: Rc<LocalConnection>,
         sig: Signal<()>,
-        api: &'static deadbeef::DB_functions_t,
+        db: Rc<dyn Backend>,
+        art: Rc<ArtFinder>,
+        inhibitor: Inhibitor,
+        #[cfg(feature = "notifications")] notifier: Option<Notifier>,
     ) -> Self {
-        Self { conn, sig, api }
+        Self {
+            #[cfg(feature = "player-extension")]
+            extras: RefCell::new(db.extras().ok()),
+            conn,
+            sig,
+            db,
+            art,
+            inhibitor,
+            #[cfg(feature = "notifications")]
+            notifier,
+        }
     }
 }
 
 impl SigHandler {
-    pub fn handle_event(&self, id: u32, ctx: usize, p1: u32, p2: u32) {
-        match id {
-            deadbeef::DB_EV_NEXT => {
-                println!("DB_EV_NEXT: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PREV => {
-                println!("DB_EV_PREV: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAY_CURRENT => {
-                println!("DB_EV_PLAY_CURRENT: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAY_NUM => {
-                println!("DB_EV_PLAY_NUM: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_STOP => {
-                println!("DB_EV_STOP: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PAUSE => {
-                println!("DB_EV_PAUSE: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAY_RANDOM => {
-                println!("DB_EV_PLAY_RANDOM: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_TERMINATE => {
-                println!("DB_EV_TERMINATE: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAYLIST_REFRESH => {
-                println!("DB_EV_PLAYLIST_REFRESH: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_REINIT_SOUND => {
-                println!("DB_EV_REINIT_SOUND: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_CONFIGCHANGED => {
-                println!("DB_EV_CONFIGCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_TOGGLE_PAUSE => {
-                println!("DB_EV_TOGGLE_PAUSE: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_ACTIVATED => {
-                println!("DB_EV_ACTIVATED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PAUSED => {
-                println!("DB_EV_PAUSED: {}, {}, {}", ctx, p1, p2);
-                let state = if p1 > 0 { "Paused" } else { "Playing" };
+    pub fn handle_event(&self, event: DeadbeefEvent) {
+        match event {
+            DeadbeefEvent::Paused(paused) => {
+                let state = if paused { "Paused" } else { "Playing" };
                 self.change_playback_status(state);
             }
-            deadbeef::DB_EV_PLAYLISTCHANGED => {
-                println!("DB_EV_PLAYLISTCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_VOLUMECHANGED => {
-                println!("DB_EV_VOLUMECHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_OUTPUTCHANGED => {
-                println!("DB_EV_OUTPUTCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAYLISTSWITCHED => {
-                println!("DB_EV_PLAYLISTSWITCHED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_SEEK => {
-                println!("DB_EV_SEEK: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_ACTIONSCHANGED => {
-                println!("DB_EV_ACTIONSCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_DSPCHAINCHANGED => {
-                println!("DB_EV_DSPCHAINCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_SELCHANGED => {
-                println!("DB_EV_SELCHANGED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLUGINSLOADED => {
-                println!("DB_EV_PLUGINSLOADED: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_FOCUS_SELECTION => {
-                println!("DB_EV_FOCUS_SELECTION: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAYBACK_STATE_DID_CHANGE => {
-                println!("DB_EV_PLAYBACK_STATE_DID_CHANGE: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAY_NEXT_ALBUM => {
-                println!("DB_EV_PLAY_NEXT_ALBUM: {}, {}, {}", ctx, p1, p2);
-            }
-            deadbeef::DB_EV_PLAY_PREV_ALBUM => {
-                println!("DB_EV_PLAY_PREV_ALBUM: {}, {}, {}", ctx, p1, p2);
+            DeadbeefEvent::SongChanged { to, .. } => {
+                debug!("song changed: {:?}", to);
+                match self.db.playing_track() {
+                    Ok(Some(track)) => {
+                        if let Err(e) = self.change_metadata(&track) {
+                            warn!("unable to update metadata: {}", e);
+                        }
+                        #[cfg(feature = "notifications")]
+                        self.notify(&track, false);
+                        self.art.request(&*self.db, &track);
+                    }
+                    // Sent with no new track on stop and at the end of
+                    // the playlist
+                    Ok(None) => self.change_playback_status("Stopped"),
+                    Err(e) => warn!("unable to get playing track: {}", e),
+                }
             }
-            deadbeef::DB_EV_PLAY_RANDOM_ALBUM => {
-                println!("DB_EV_PLAY_RANDOM_ALBUM: {}, {}, {}", ctx, p1, p2);
+            DeadbeefEvent::TrackInfoChanged(changed) => match self.db.playing_track() {
+                // Sent for tag edits, ReplayGain scans, and by streams for
+                // each new song. Only the playing track's changes are shown.
+                Ok(Some(track)) if changed.as_ref().map_or(true, |t| t.id() == track.id) => {
+                    if let Err(e) = self.change_metadata(&track) {
+                        warn!("unable to update metadata: {}", e);
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("unable to get playing track: {}", e),
+            },
+            #[cfg(feature = "queue")]
+            DeadbeefEvent::PlaylistChanged(PlaylistChange::PlayQueue)
+                if self.db.has(Feature::PlayQueue) =>
+            {
+                if let Err(e) = super::queue::emit_changed(&self.conn, &*self.db) {
+                    warn!("unable to update the play queue: {}", e);
+                }
             }
-            // DB_EV_FIRST == DB_EV_SONGCHANGED
-            deadbeef::DB_EV_SONGCHANGED => {
-                let event =
-                    unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_trackchange_t) };
-                self.change_metadata(event.to as usize).unwrap();
-                println!("song changed: {:?}, {}, {}", event.to as usize, p1, p2);
+            #[cfg(feature = "player-extension")]
+            DeadbeefEvent::ConfigChanged => {
+                if let Err(e) = self.change_extras() {
+                    warn!("{}", e);
+                }
             }
-            deadbeef::DB_EV_SONGSTARTED => {
-                let event = unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_track_t) };
+            DeadbeefEvent::Seeked { position, .. } => self.seeked(position),
+            DeadbeefEvent::VolumeChanged => self.change_volume(),
+            DeadbeefEvent::SongStarted(track) => {
                 self.change_playback_status("Playing");
-                println!("song started: {:?}, {}, {}", event, p1, p2);
-            }
-            deadbeef::DB_EV_SONGFINISHED => {
-                let event = unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_track_t) };
-                println!("song finished: {:?}, {}, {}", event, p1, p2);
+                debug!("song started: {:?}", track);
             }
-            deadbeef::DB_EV_TRACKINFOCHANGED => {
-                let event = unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_track_t) };
-                println!(
-                    "track info changed: {:?}, {}, {}",
-                    event.track as usize, p1, p2
-                );
-            }
-            deadbeef::DB_EV_SEEKED => {
-                let event = unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_playpos_t) };
-                println!("track seeked changed: {:?}, {}, {}", event, p1, p2);
-            }
-            deadbeef::DB_EV_CURSOR_MOVED => {
-                let event = unsafe { std::ptr::read(ctx as *const deadbeef::ddb_event_track_t) };
-                println!("cursor moved: {:?}, {}, {}", event, p1, p2);
-            }
-            _ => {
-                eprintln!(
+            DeadbeefEvent::Unknown { id, ctx, p1, p2 } => {
+                debug!(
                     "received unknown message: id: {}, ctx: {}, p1: {}, p2: {}",
                     id, ctx, p1, p2
                 );
             }
+            event => {
+                trace!("{:?}", event);
+            }
+        }
+    }
+
+    /// Re-sends the metadata of the playing track if the artwork plugin
+    /// has found its cover in the meantime.
+    pub fn update_art(&self) {
+        let arrived = self.art.arrived();
+        if arrived.is_empty() {
+            return;
+        }
+
+        match self.db.playing_track() {
+            Ok(Some(track)) if arrived.contains(&track.id) => {
+                if let Err(e) = self.change_metadata(&track) {
+                    warn!("unable to update album art: {}", e);
+                }
+                #[cfg(feature = "notifications")]
+                self.notify(&track, true);
+            }
+            Ok(_) => {}
+            Err(e) => warn!("unable to get playing track: {}", e),
         }
     }
 
     fn change_playback_status(&self, state: &str) {
+        self.inhibitor.set_playing(state == "Playing");
+
         let mut props = PropMap::new();
         props.insert(
             "PlaybackStatus".to_owned(),
             Variant(Box::new(state.to_owned())),
         );
 
-        self.conn
-            .send(
-                self.sig
-                    .msg(
-                        &Path::from_slice("/org/mpris/MediaPlayer2").unwrap(),
-                        &Interface::new("org.freedesktop.DBus.Properties".to_string()).unwrap(),
-                    )
-                    .append3(
-                        "org.mpris.MediaPlayer2.Player",
-                        props,
-                        Array::new(Vec::<String>::new()),
-                    ),
-            )
-            .unwrap();
+        if self
+            .properties_changed("org.mpris.MediaPlayer2.Player", props)
+            .is_err()
+        {
+            warn!("unable to send PlaybackStatus change");
+        }
     }
 
-    fn change_metadata(&self, track_id: usize) -> Result<(), String> {
-        let mut metadata = PropMap::new();
+    fn change_volume(&self) {
+        let volume = match self.db.volume() {
+            Ok(volume) => volume,
+            Err(e) => {
+                warn!("unable to get volume: {}", e);
+                return;
+            }
+        };
 
-        metadata.insert(
-            "mpris:trackid".to_string(),
-            Variant(Box::new(format!(
-                "/org/mpris/MediaPlayer2/tracks/{}",
-                track_id
-            ))),
-        );
-
-        unsafe {
-            let get_track_fn = self
-                .api
-                .streamer_get_playing_track
-                .ok_or_else(|| "could not get playing track".to_owned())?;
-
-            let track = get_track_fn();
-
-            if track.is_null() {
-                return Ok(());
-            }
-
-            let _l = Lock::new(self.api);
-
-            let get_meta_fn = self
-                .api
-                .pl_get_metadata_head
-                .ok_or_else(|| "could not get metadata head for track".to_string())?;
+        let mut props = PropMap::new();
+        props.insert("Volume".to_owned(), Variant(Box::new(volume)));
+        if self
+            .properties_changed("org.mpris.MediaPlayer2.Player", props)
+            .is_err()
+        {
+            warn!("unable to send Volume change");
+        }
+    }
 
-            let mut meta = get_meta_fn(track)
-                .as_ref()
-                .ok_or_else(|| "null metadata returned".to_string())?;
+    /// Sends the `Seeked` signal for a seek to `position`, in seconds.
+    fn seeked(&self, position: f32) {
+        let position = (f64::from(position) * 1_000_000.0) as i64;
+        let msg = Message::new_signal(
+            "/org/mpris/MediaPlayer2",
+            "org.mpris.MediaPlayer2.Player",
+            "Seeked",
+        )
+        .expect("the Seeked signal is valid")
+        .append1(position);
 
-            loop {
-                let key = std::ffi::CStr::from_ptr(meta.key).to_str().unwrap();
-                let val = std::ffi::CStr::from_ptr(meta.value).to_str().unwrap();
+        if self.conn.send(msg).is_err() {
+            warn!("unable to send Seeked signal");
+        }
+    }
 
-                match key.to_lowercase().as_str() {
-                    "artist" => {
-                        metadata.insert(
-                            "xesam:artist".to_string(),
-                            Variant(Box::new(val.to_string())),
-                        );
-                    }
-                    "album artist" => {
-                        metadata.insert(
-                            "xesam:albumArtist".to_string(),
-                            Variant(Box::new(val.to_string())),
-                        );
-                    }
-                    "album" => {
-                        metadata.insert(
-                            "xesam:album".to_string(),
-                            Variant(Box::new(val.to_string())),
-                        );
-                    }
-                    "title" => {
-                        metadata.insert(
-                            "xesam:title".to_string(),
-                            Variant(Box::new(val.to_string())),
-                        );
-                    }
-                    ":uri" => {
-                        let (path, file_uri) = if val.starts_with("file://") {
-                            (
-                                std::path::Path::new(val.strip_prefix("file://").unwrap()),
-                                val.to_string(),
-                            )
-                        } else {
-                            (std::path::Path::new(val), format!("file://{}", val))
-                        };
+    /// Announces the metadata of `track`, along with whether it can be
+    /// seeked, which differs between files and streams.
+    fn change_metadata(&self, track: &Track) -> Result<(), String> {
+        let metadata = track_metadata(track, &self.art);
 
-                        metadata
-                            .insert("xesam:url".to_string(), Variant(Box::new(file_uri.clone())));
+        let mut props = PropMap::new();
+        props.insert("Metadata".to_owned(), Variant(Box::new(metadata)));
+        props.insert("CanSeek".to_owned(), Variant(Box::new(can_seek(track))));
+        self.properties_changed("org.mpris.MediaPlayer2.Player", props)
+            .map_err(|_| "unable to send Metadata change".to_string())
+    }
 
-                        let art_uri = album_art_from_file(path);
+    /// Shows `track` in a desktop notification, if enabled. With
+    /// `replace_only`, only a notification already showing it is updated.
+    #[cfg(feature = "notifications")]
+    fn notify(&self, track: &Track, replace_only: bool) {
+        let notifier = match &self.notifier {
+            Some(notifier) => notifier,
+            None => return,
+        };
+        if replace_only && !notifier.showing(track.id) {
+            return;
+        }
 
-                        println!("art uri: {:?}", &art_uri);
-                        if let Some(uri) = art_uri {
-                            metadata.insert("mpris:artUrl".to_string(), Variant(Box::new(uri)));
-                        };
-                    }
-                    ":duration" => {
-                        let dur = val
-                            .split(':')
-                            .rev()
-                            .enumerate()
-                            .fold(0i64, |acc, (idx, v)| {
-                                acc + ((60 * idx as i64) * v.parse::<i64>().unwrap())
-                            })
-                            * 1000
-                            * 1000;
+        let metadata = track_metadata(track, &self.art);
+        if let Err(e) = notifier.notify(track, &metadata) {
+            warn!("unable to show notification: {}", e);
+        }
+    }
 
-                        metadata.insert("mpris:length".to_string(), Variant(Box::new(dur)));
-                    }
-                    _ => {}
-                };
+    /// Announces the extension properties that changed since the last call.
+    #[cfg(feature = "player-extension")]
+    fn change_extras(&self) -> Result<(), String> {
+        let extras = self
+            .db
+            .extras()
+            .map_err(|e| format!("unable to read playback options: {}", e))?;
+        let previous = self.extras.replace(Some(extras));
 
-                println!("Key: {}, Val: {}", key, val);
-                if meta.next.is_null() {
-                    break;
-                }
-                meta = meta.next.as_ref().unwrap();
-            }
+        let props = player_extension::changed_properties(previous.as_ref(), &extras);
+        if props.is_empty() {
+            return Ok(());
         }
+        self.properties_changed(player_extension::INTERFACE, props)
+            .map_err(|_| "unable to send playback option changes".to_string())
+    }
 
-        let mut props = PropMap::new();
-        props.insert("Metadata".to_owned(), Variant(Box::new(metadata)));
+    /// Sends `PropertiesChanged` for `interface` on the MPRIS object.
+    fn properties_changed(&self, interface: &str, props: PropMap) -> Result<(), ()> {
         self.conn
             .send(
                 self.sig
@@ -294,33 +266,8 @@ impl SigHandler {
                         &Path::from_slice("/org/mpris/MediaPlayer2").unwrap(),
                         &Interface::new("org.freedesktop.DBus.Properties".to_string()).unwrap(),
                     )
-                    .append3(
-                        "org.mpris.MediaPlayer2.Player",
-                        props,
-                        Array::new(Vec::<String>::new()),
-                    ),
+                    .append3(interface, props, Array::new(Vec::<String>::new())),
             )
-            .unwrap();
-
-        Ok(())
+            .map(|_| ())
     }
 }
-
-fn album_art_from_file(file_path: &std::path::Path) -> Option<String> {
-    let dir = match file_path.parent() {
-        Some(d) => d,
-        None => return None,
-    };
-    std::fs::read_dir(dir).unwrap().find_map(|f| {
-        if let Ok(file) = f {
-            match file.file_name().to_ascii_lowercase().to_str().unwrap() {
-                "folder.jpg" | "folder.png" => {
-                    Some(format!("file://{}", file.path().to_str().unwrap()))
-                }
-                _ => None,
-            }
-        } else {
-            None
-        }
-    })
-}
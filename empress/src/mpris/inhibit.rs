@@ -0,0 +1,129 @@
+//! Keeps the computer awake during playback, with a lock from logind or
+//! the desktop's screen saver.
+use std::{cell::RefCell, rc::Rc, time::Duration};
+
+use dbus::{
+    arg::OwnedFd,
+    blocking::{LocalConnection, Proxy},
+};
+
+use crate::{debug, settings::Inhibit, warn};
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+const WHO: &str = "DeaDBeeF";
+const WHY: &str = "Playing music";
+
+/// A lock held by the player.
+enum Lock {
+    /// logind releases the lock once every copy of the descriptor is closed
+    Login1(OwnedFd),
+    /// Cookie to pass to `UnInhibit`
+    ScreenSaver(u32),
+}
+
+/// Takes and releases the lock as playback starts and stops.
+///
+/// The calls block for up to [`TIMEOUT`], so the inhibitor is only used on
+/// the listener thread, and dropped once the listener has stopped.
+pub(super) struct Inhibitor {
+    method: Inhibit,
+    session: Rc<LocalConnection>,
+    /// Connection to the system bus, opened when logind is first asked
+    system: RefCell<Option<LocalConnection>>,
+    lock: RefCell<Option<Lock>>,
+}
+
+impl Inhibitor {
+    pub fn new(method: Inhibit, session: Rc<LocalConnection>) -> Self {
+        Self {
+            method,
+            session,
+            system: RefCell::new(None),
+            lock: RefCell::new(None),
+        }
+    }
+
+    /// Holds a lock while `playing`, and releases it otherwise.
+    pub fn set_playing(&self, playing: bool) {
+        let held = self.lock.borrow().is_some();
+        if self.method == Inhibit::Off || playing == held {
+            return;
+        }
+
+        if playing {
+            match self.acquire() {
+                Ok(lock) => {
+                    debug!("inhibiting sleep through {:?}", self.method);
+                    *self.lock.borrow_mut() = Some(lock);
+                }
+                Err(e) => warn!("unable to inhibit sleep: {}", e),
+            }
+        } else if let Some(lock) = self.lock.borrow_mut().take() {
+            debug!("releasing sleep inhibitor");
+            if let Err(e) = self.release(lock) {
+                warn!("unable to release sleep inhibitor: {}", e);
+            }
+        }
+    }
+
+    fn acquire(&self) -> Result<Lock, dbus::Error> {
+        match self.method {
+            Inhibit::Login1 => {
+                let mut system = self.system.borrow_mut();
+                if system.is_none() {
+                    *system = Some(LocalConnection::new_system()?);
+                }
+                let proxy = system.as_ref().unwrap().with_proxy(
+                    "org.freedesktop.login1",
+                    "/org/freedesktop/login1",
+                    TIMEOUT,
+                );
+                let (fd,): (OwnedFd,) = proxy.method_call(
+                    "org.freedesktop.login1.Manager",
+                    "Inhibit",
+                    ("sleep:idle", WHO, WHY, "block"),
+                )?;
+                Ok(Lock::Login1(fd))
+            }
+            Inhibit::ScreenSaver => {
+                let (cookie,): (u32,) = self.screen_saver().method_call(
+                    "org.freedesktop.ScreenSaver",
+                    "Inhibit",
+                    (WHO, WHY),
+                )?;
+                Ok(Lock::ScreenSaver(cookie))
+            }
+            Inhibit::Off => Err(dbus::Error::new_failed("sleep inhibition is off")),
+        }
+    }
+
+    fn release(&self, lock: Lock) -> Result<(), dbus::Error> {
+        match lock {
+            Lock::Login1(fd) => {
+                drop(fd);
+                Ok(())
+            }
+            Lock::ScreenSaver(cookie) => self.screen_saver().method_call(
+                "org.freedesktop.ScreenSaver",
+                "UnInhibit",
+                (cookie,),
+            ),
+        }
+    }
+
+    fn screen_saver(&self) -> Proxy<'_, &LocalConnection> {
+        self.session.with_proxy(
+            "org.freedesktop.ScreenSaver",
+            "/org/freedesktop/ScreenSaver",
+            TIMEOUT,
+        )
+    }
+}
+
+impl Drop for Inhibitor {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock.get_mut().take() {
+            let _ = self.release(lock);
+        }
+    }
+}
@@ -101,15 +101,13 @@ impl MediaPlayer {
 impl MediaPlayer {
     /// Raise() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Media_Player.html#Method:Raise
-    fn raise(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        println!("Raise called: {:?}", m);
+    fn raise(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
         Ok(vec![])
     }
 
     /// Quit() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Media_Player.html#Method:Quit
-    fn quit(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        println!("Quit called: {:?}", m);
+    fn quit(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
         Ok(vec![])
     }
 }
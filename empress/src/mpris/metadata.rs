@@ -0,0 +1,192 @@
+//! Conversion of DeaDBeeF tracks to MPRIS metadata.
+//!
+//! https://www.freedesktop.org/wiki/Specifications/mpris-spec/metadata
+use dbus::{
+    arg::{PropMap, Variant},
+    Path,
+};
+
+use crate::{
+    art::ArtFinder,
+    backend::Track,
+    trace,
+    uri::{scheme, Location},
+    warn,
+};
+
+/// D-Bus object path identifying the track `id` in `mpris:trackid`.
+pub(super) fn track_path(id: usize) -> Path<'static> {
+    Path::new(format!("/org/mpris/MediaPlayer2/tracks/{}", id))
+        .expect("track paths are always valid")
+}
+
+/// The track id in a path made by [`track_path`].
+pub(super) fn track_id(path: &Path) -> Option<usize> {
+    path.strip_prefix("/org/mpris/MediaPlayer2/tracks/")?
+        .parse()
+        .ok()
+}
+
+/// URI schemes of internet radio and other live streams.
+const STREAM_SCHEMES: &[&str] = &["http", "https", "mms", "mmsh", "rtsp", "rtmp"];
+
+/// Whether `track` is a live stream, such as internet radio.
+fn is_stream(track: &Track) -> bool {
+    track
+        .metadata
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(":uri"))
+        .map_or(false, |(_, uri)| match Location::parse(uri) {
+            Location::Other(uri) => scheme(&uri).map_or(false, |scheme| {
+                STREAM_SCHEMES
+                    .iter()
+                    .any(|s| s.eq_ignore_ascii_case(scheme))
+            }),
+            Location::File(_) => false,
+        })
+}
+
+/// Length of `track` in microseconds, or `None` for streams and tracks
+/// without a valid duration.
+pub(super) fn track_length(track: &Track) -> Option<i64> {
+    if is_stream(track) {
+        return None;
+    }
+    let (_, duration) = track
+        .metadata
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(":duration"))?;
+    parse_duration(duration).filter(|&length| length > 0)
+}
+
+/// Whether `track` can be seeked, which needs a known length.
+pub(super) fn can_seek(track: &Track) -> bool {
+    track_length(track).is_some()
+}
+
+/// Builds the Metadata map for `track`.
+///
+/// For streams, DeaDBeeF keeps the station name from the `icy-name` header
+/// in `album` and the song announced in the stream in `!title` and
+/// `!artist`, which take precedence over the station's own tags. Streams
+/// have no length. Tags rendered by title-format scripts replace the raw
+/// values, and `xesam:userRating` comes from the configured rating tags.
+pub(super) fn track_metadata(track: &Track, art: &ArtFinder) -> PropMap {
+    let mut metadata = PropMap::new();
+    let stream = is_stream(track);
+
+    metadata.insert(
+        "mpris:trackid".to_string(),
+        Variant(Box::new(track_path(track.id))),
+    );
+
+    for (key, val) in &track.metadata {
+        let val = val.as_str();
+
+        match key.to_lowercase().as_str() {
+            "artist" => {
+                metadata
+                    .entry("xesam:artist".to_string())
+                    .or_insert_with(|| Variant(Box::new(vec![val.to_string()])));
+            }
+            "!artist" => {
+                metadata.insert(
+                    "xesam:artist".to_string(),
+                    Variant(Box::new(vec![val.to_string()])),
+                );
+            }
+            "album artist" => {
+                metadata.insert(
+                    "xesam:albumArtist".to_string(),
+                    Variant(Box::new(vec![val.to_string()])),
+                );
+            }
+            "album" => {
+                metadata.insert(
+                    "xesam:album".to_string(),
+                    Variant(Box::new(val.to_string())),
+                );
+            }
+            "title" => {
+                metadata
+                    .entry("xesam:title".to_string())
+                    .or_insert_with(|| Variant(Box::new(val.to_string())));
+            }
+            "!title" => {
+                metadata.insert(
+                    "xesam:title".to_string(),
+                    Variant(Box::new(val.to_string())),
+                );
+            }
+            "genre" => {
+                metadata.insert(
+                    "xesam:genre".to_string(),
+                    Variant(Box::new(vec![val.to_string()])),
+                );
+            }
+            ":bitrate" => match val.trim().parse::<i32>() {
+                Ok(kbps) => {
+                    metadata.insert("deadbeef:bitrate".to_string(), Variant(Box::new(kbps)));
+                }
+                Err(_) => warn!("invalid bitrate {:?}", val),
+            },
+            ":uri" => {
+                let location = Location::parse(val);
+                metadata.insert(
+                    "xesam:url".to_string(),
+                    Variant(Box::new(location.to_uri())),
+                );
+
+                let track_path = match &location {
+                    Location::File(path) => Some(path.as_path()),
+                    Location::Other(_) => None,
+                };
+                let art_uri = art.find(track, track_path);
+
+                trace!("art uri: {:?}", &art_uri);
+                if let Some(uri) = art_uri {
+                    metadata.insert("mpris:artUrl".to_string(), Variant(Box::new(uri)));
+                };
+            }
+            ":duration" if stream => {}
+            ":duration" => match parse_duration(val) {
+                Some(dur) => {
+                    metadata.insert("mpris:length".to_string(), Variant(Box::new(dur)));
+                }
+                None => warn!("invalid duration {:?}", val),
+            },
+            _ => {}
+        };
+
+        trace!("{}: {}", key, val);
+    }
+
+    let formatted = &track.formatted;
+    if let Some(title) = &formatted.title {
+        metadata.insert("xesam:title".to_string(), Variant(Box::new(title.clone())));
+    }
+    if let Some(artist) = &formatted.artist {
+        metadata.insert(
+            "xesam:artist".to_string(),
+            Variant(Box::new(vec![artist.clone()])),
+        );
+    }
+    if let Some(album) = &formatted.album {
+        metadata.insert("xesam:album".to_string(), Variant(Box::new(album.clone())));
+    }
+
+    if let Some(rating) = track.rating {
+        metadata.insert("xesam:userRating".to_string(), Variant(Box::new(rating)));
+    }
+
+    metadata
+}
+
+/// Parses a `[[h:]m:]s` duration into microseconds.
+fn parse_duration(val: &str) -> Option<i64> {
+    let secs = val.split(':').try_fold(0f64, |acc, v| {
+        v.trim().parse::<f64>().ok().map(|v| acc * 60.0 + v)
+    })?;
+
+    Some((secs * 1_000_000.0) as i64)
+}
@@ -1,6 +1,24 @@
+use dbus::MethodErr;
+
+use crate::deadbeef;
+
 mod change_signals;
+mod inhibit;
 mod media_player;
+mod metadata;
 mod mpris_registration;
+#[cfg(feature = "notifications")]
+mod notifications;
 mod player;
+#[cfg(feature = "player-extension")]
+mod player_extension;
+#[cfg(feature = "queue")]
+mod queue;
 
 pub use mpris_registration::MPRIS;
+
+impl From<deadbeef::Error> for MethodErr {
+    fn from(e: deadbeef::Error) -> Self {
+        MethodErr::failed(&e)
+    }
+}
@@ -1,20 +1,48 @@
 use std::{
+    cell::RefCell,
     rc::Rc,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
     time::Duration,
 };
 
-use dbus::blocking::LocalConnection;
+use dbus::{
+    blocking::{stdintf::org_freedesktop_dbus::RequestNameReply, LocalConnection},
+    channel::{MatchingReceiver, Sender},
+    message::{MatchRule, MessageType},
+};
 use dbus_tree::Factory;
 
-use crate::deadbeef;
+use crate::{
+    art::ArtFinder,
+    backend::Backend,
+    deadbeef::{DeadbeefEvent, Feature, OutputState},
+    debug, error, info,
+    settings::Settings,
+    warn,
+};
 
-use super::{change_signals::SigHandler, media_player::MediaPlayer, player::Player};
+#[cfg(feature = "notifications")]
+use super::notifications::Notifier;
+#[cfg(feature = "player-extension")]
+use super::player_extension::PlayerExtension;
+#[cfg(feature = "queue")]
+use super::queue::Queue;
+use super::{
+    change_signals::SigHandler, inhibit::Inhibitor, media_player::MediaPlayer, player::Player,
+};
 
 pub struct MPRIS {
     pub(super) conn: Option<Rc<LocalConnection>>,
     pub(super) sig_handler: Option<SigHandler>,
     pub(super) exit: AtomicBool,
+    /// Events waiting for the listener thread
+    pub(super) events: Mutex<Vec<DeadbeefEvent>>,
+    pub(super) bus_name: Option<Rc<RefCell<String>>>,
+    pub(super) db: Option<Rc<dyn Backend>>,
+    pub(super) settings: Option<Settings>,
 }
 
 impl MPRIS {
@@ -23,53 +51,359 @@ impl MPRIS {
             conn: None,
             sig_handler: None,
             exit: AtomicBool::new(false),
+            events: Mutex::new(Vec::new()),
+            bus_name: None,
+            db: None,
+            settings: None,
         }
     }
 
-    pub fn init(&mut self, name: &str, api: &'static deadbeef::DB_functions_t) {
-        let conn = LocalConnection::new_session().unwrap();
+    /// Connects to the session bus and registers the MPRIS object tree.
+    ///
+    /// The well-known `name` is requested first. If another player (such as
+    /// a second DeaDBeeF instance) already owns it, the instance name
+    /// `<name>.<suffix>` is used instead, defaulting to `instance<PID>` as
+    /// recommended by the MPRIS specification.
+    pub fn init(
+        &mut self,
+        name: &str,
+        settings: &Settings,
+        db: Rc<dyn Backend>,
+    ) -> Result<(), dbus::Error> {
+        self.init_on(LocalConnection::new_session()?, name, settings, db)
+    }
+
+    /// As [`MPRIS::init`], but registers on an existing connection, such as
+    /// one to a private bus.
+    pub fn init_on(
+        &mut self,
+        conn: LocalConnection,
+        name: &str,
+        settings: &Settings,
+        db: Rc<dyn Backend>,
+    ) -> Result<(), dbus::Error> {
+        self.exit.store(false, Ordering::SeqCst);
+
+        let suffix = settings.instance_suffix.as_deref();
+        let instance_name = format!("{}.{}", name, instance_suffix(suffix));
+        let bus_name = Rc::new(RefCell::new(acquire_name(&conn, name, &instance_name)?));
+        info!("registered as {}", bus_name.borrow());
+
+        watch_name(&conn, &bus_name, instance_name)?;
+        self.bus_name = Some(bus_name);
 
-        conn.request_name(name, true, true, false).unwrap();
+        if let Err(e) = db.set_title_formats(&settings.title_formats) {
+            warn!("unable to compile title formats: {}", e);
+        }
+        if let Err(e) = db.set_rating_tags(&settings.rating_tags) {
+            warn!("unable to set rating tags: {}", e);
+        }
+        self.db = Some(Rc::clone(&db));
+
+        let art = Rc::new(ArtFinder::new(&settings.art));
         let f = Factory::new_fn::<()>();
 
-        let tree = f.tree(()).add(
-            f.object_path("/org/mpris/MediaPlayer2", ())
-                .introspectable()
-                .add(MediaPlayer::from_factory(&f))
-                .add(Player::from_factory(&f, api)),
-        );
+        let object = f
+            .object_path("/org/mpris/MediaPlayer2", ())
+            .introspectable()
+            .add(MediaPlayer::from_factory(&f))
+            .add(Player::from_factory(&f, Rc::clone(&db), Rc::clone(&art)));
+        #[cfg(feature = "queue")]
+        let object = if db.has(Feature::PlayQueue) {
+            object.add(Queue::from_factory(&f, Rc::clone(&db), Rc::clone(&art)))
+        } else {
+            object
+        };
+        #[cfg(feature = "player-extension")]
+        let object = object.add(PlayerExtension::from_factory(&f, Rc::clone(&db)));
+        let tree = f.tree(()).add(object);
+        info!("serving {}", features_in_use(&*db));
 
-        tree.start_receive(&conn);
+        conn.start_receive(
+            MatchRule::new_method_call(),
+            Box::new(move |msg, conn| {
+                debug!(
+                    "{} {}.{} from {}",
+                    msg.path().as_deref().unwrap_or(""),
+                    msg.interface().as_deref().unwrap_or(""),
+                    msg.member().as_deref().unwrap_or(""),
+                    msg.sender().as_deref().unwrap_or("")
+                );
+                if let Some(replies) = tree.handle(&msg) {
+                    for reply in replies {
+                        if reply.msg_type() == MessageType::Error {
+                            debug!("replying with an error: {:?}", reply);
+                        }
+                        let _ = conn.send(reply);
+                    }
+                }
+                true
+            }),
+        );
 
         let conn_rc = Rc::new(conn);
         self.conn = Some(Rc::clone(&conn_rc));
 
+        let inhibitor = Inhibitor::new(settings.inhibit, Rc::clone(&conn_rc));
+
+        #[cfg(feature = "notifications")]
+        let notifier = if settings.notify {
+            match Notifier::new(Rc::clone(&conn_rc), Rc::clone(&db)) {
+                Ok(notifier) => Some(notifier),
+                Err(e) => {
+                    warn!("unable to set up notifications: {}", e);
+                    None
+                }
+            }
+        } else {
+            None
+        };
+
+        let playing = db.output_state() == Ok(OutputState::Playing);
         self.sig_handler = Some(SigHandler::new(
             Rc::clone(&conn_rc),
             f.signal("PropertiesChanged", ()),
-            api,
+            db,
+            art,
+            inhibitor,
+            #[cfg(feature = "notifications")]
+            notifier,
         ));
+        self.settings = Some(settings.clone());
+
+        // Later changes arrive as events, but the player may already be
+        // playing when the service is registered again. The lock is then
+        // taken by the listener, like for any other event.
+        if playing {
+            self.handle_event(DeadbeefEvent::Paused(false));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the service is registered on the bus.
+    pub fn is_running(&self) -> bool {
+        self.conn.is_some()
+    }
+
+    /// Unregisters the service, releasing its bus name.
+    ///
+    /// [`MPRIS::listen`] must have returned first, as the connection it
+    /// uses is closed.
+    pub fn shutdown(&mut self) {
+        self.take_events();
+        self.sig_handler = None;
+        self.conn = None;
+        self.db = None;
+        self.bus_name = None;
+        self.settings = None;
     }
 
-    pub fn handle_event(&self, id: u32, ctx: usize, p1: u32, p2: u32) {
-        self.sig_handler
-            .as_ref()
-            .unwrap()
-            .handle_event(id, ctx, p1, p2)
+    /// The bus name currently owned by this player, if registered.
+    pub fn bus_name(&self) -> Option<String> {
+        self.bus_name.as_ref().map(|n| n.borrow().clone())
+    }
+
+    /// Describes the service's state in a few lines, for diagnostics.
+    pub fn describe(&self) -> String {
+        let mut lines = vec![
+            format!("running: {}", self.is_running()),
+            format!("bus name: {:?}", self.bus_name()),
+            format!("settings: {:?}", self.settings),
+        ];
+        if let Some(db) = self.db.as_ref() {
+            lines.push(format!("output: {:?}", db.output_state()));
+            lines.push(format!("playing: {:?}", db.playing_track()));
+        }
+        lines.join("\n")
+    }
+
+    /// Applies `settings` to the running service.
+    ///
+    /// Title formats, rating tags and the log level are applied in place. Returns
+    /// `false` if anything else changed, in which case the service has to
+    /// be registered again.
+    pub fn reconfigure(&mut self, settings: &Settings) -> bool {
+        let (current, db) = match (self.settings.as_mut(), self.db.as_ref()) {
+            (Some(current), Some(db)) => (current, db),
+            _ => return false,
+        };
+
+        let in_place = Settings {
+            title_formats: settings.title_formats.clone(),
+            rating_tags: settings.rating_tags.clone(),
+            log_level: settings.log_level,
+            ..current.clone()
+        };
+        if in_place != *settings {
+            return false;
+        }
+
+        if current.title_formats != settings.title_formats {
+            if let Err(e) = db.set_title_formats(&settings.title_formats) {
+                warn!("unable to compile title formats: {}", e);
+            }
+            current.title_formats = settings.title_formats.clone();
+        }
+        if current.rating_tags != settings.rating_tags {
+            if let Err(e) = db.set_rating_tags(&settings.rating_tags) {
+                warn!("unable to set rating tags: {}", e);
+            }
+            current.rating_tags = settings.rating_tags.clone();
+        }
+        current.log_level = settings.log_level;
+        true
+    }
+
+    /// Queues `event` to be handled by [`MPRIS::process`].
+    ///
+    /// DeaDBeeF sends its messages on its own thread, while the connection
+    /// and everything the signals are built from belong to the thread
+    /// running [`MPRIS::listen`], so events are only handled there.
+    pub fn handle_event(&self, event: DeadbeefEvent) {
+        // Events can arrive before `init`, or after it failed
+        if self.is_running() {
+            self.events
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .push(event);
+        }
+    }
+
+    fn take_events(&self) -> Vec<DeadbeefEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap_or_else(|e| e.into_inner()))
     }
 
     pub fn listen(&self) {
         while !self.exit.load(Ordering::SeqCst) {
-            self.conn
-                .as_ref()
-                .unwrap()
-                .process(Duration::from_millis(100))
-                .unwrap();
+            if let Err(e) = self.process(Duration::from_millis(100)) {
+                error!("failed to process D-Bus messages: {}", e);
+                break;
+            }
         }
     }
 
+    /// Handles at most one incoming D-Bus message, waiting up to `timeout`
+    /// for it to arrive, then the queued events, and publishes any album
+    /// art found meanwhile.
+    pub fn process(&self, timeout: Duration) -> Result<bool, dbus::Error> {
+        let conn = match self.conn.as_ref() {
+            Some(conn) => conn,
+            None => return Err(dbus::Error::new_failed("MPRIS service is not initialised")),
+        };
+        let processed = conn.process(timeout)?;
+
+        if let Some(sig_handler) = self.sig_handler.as_ref() {
+            for event in self.take_events() {
+                sig_handler.handle_event(event);
+            }
+            sig_handler.update_art();
+        }
+        Ok(processed)
+    }
+
     pub fn exit(&mut self) {
-        println!("exit called");
+        debug!("exit called");
         self.exit.store(true, Ordering::SeqCst)
     }
 }
+
+/// Lists the optional features the service uses and those the player
+/// lacks, whose parts of the service are left out.
+fn features_in_use(db: &dyn Backend) -> String {
+    let features = [
+        Feature::TitleFormat,
+        Feature::Shuffle,
+        #[cfg(feature = "queue")]
+        Feature::PlayQueue,
+    ];
+    features
+        .iter()
+        .map(|feature| {
+            if db.has(*feature) {
+                feature.to_string()
+            } else {
+                format!("no {}", feature)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Returns the suffix appended to the base bus name when it is already taken.
+fn instance_suffix(suffix: Option<&str>) -> String {
+    match suffix.map(str::trim) {
+        Some(s) if !s.is_empty() => s.to_string(),
+        _ => format!("instance{}", std::process::id()),
+    }
+}
+
+/// Requests `name` without replacing its current owner, falling back to
+/// `instance_name` when the name is already in use.
+fn acquire_name(
+    conn: &LocalConnection,
+    name: &str,
+    instance_name: &str,
+) -> Result<String, dbus::Error> {
+    match conn.request_name(name, false, false, true)? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => Ok(name.to_string()),
+        RequestNameReply::Exists | RequestNameReply::InQueue => {
+            info!(
+                "{} is already owned, falling back to {}",
+                name, instance_name
+            );
+
+            match conn.request_name(instance_name, false, false, true)? {
+                RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => {
+                    Ok(instance_name.to_string())
+                }
+                reply => Err(dbus::Error::new_failed(&format!(
+                    "unable to acquire {}: {:?}",
+                    instance_name, reply
+                ))),
+            }
+        }
+    }
+}
+
+/// Tracks ownership of the player's bus name.
+///
+/// If the bus takes the name away, the instance name is requested so the
+/// player stays reachable.
+fn watch_name(
+    conn: &LocalConnection,
+    bus_name: &Rc<RefCell<String>>,
+    instance_name: String,
+) -> Result<(), dbus::Error> {
+    let rc = Rc::clone(bus_name);
+    conn.add_match(
+        MatchRule::new_signal("org.freedesktop.DBus", "NameLost"),
+        move |(lost,): (String,), conn: &LocalConnection, _| {
+            if *rc.borrow() != lost {
+                return true;
+            }
+            warn!("lost bus name {}", lost);
+
+            if lost != instance_name {
+                if let Err(e) = conn.request_name(instance_name.as_str(), false, false, true) {
+                    error!("unable to request {}: {}", instance_name, e);
+                }
+            }
+            true
+        },
+    )?;
+
+    let rc = Rc::clone(bus_name);
+    conn.add_match(
+        MatchRule::new_signal("org.freedesktop.DBus", "NameAcquired"),
+        move |(acquired,): (String,), _: &LocalConnection, _| {
+            if acquired.starts_with("org.mpris.MediaPlayer2.") {
+                info!("acquired bus name {}", acquired);
+                *rc.borrow_mut() = acquired;
+            }
+            true
+        },
+    )?;
+
+    Ok(())
+}
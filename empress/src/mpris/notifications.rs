@@ -0,0 +1,137 @@
+//! Desktop notifications for track changes, through
+//! `org.freedesktop.Notifications`.
+//!
+//! https://specifications.freedesktop.org/notification-spec/latest/
+use std::{cell::Cell, rc::Rc, time::Duration};
+
+use dbus::{
+    arg::{prop_cast, PropMap, Variant},
+    blocking::LocalConnection,
+    message::MatchRule,
+    Message,
+};
+
+use crate::{
+    backend::{Backend, Track},
+    deadbeef::Command,
+    warn,
+};
+
+const INTERFACE: &str = "org.freedesktop.Notifications";
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Action keys and labels of the notification's buttons.
+const ACTIONS: [&str; 4] = ["next", "Next", "pause", "Pause"];
+
+/// Shows the playing track, replacing its own previous notification.
+///
+/// `Notify` blocks for up to [`TIMEOUT`], and the on-screen id is shared
+/// with the button handler, so the notifier is only used on the listener
+/// thread, where both run.
+pub(super) struct Notifier {
+    conn: Rc<LocalConnection>,
+    /// Id of the notification on screen, replaced by the next one
+    id: Rc<Cell<u32>>,
+    /// The track it shows
+    track: Cell<Option<usize>>,
+}
+
+impl Notifier {
+    /// Starts listening for the notification's buttons, which control the
+    /// player through `db`.
+    pub fn new(conn: Rc<LocalConnection>, db: Rc<dyn Backend>) -> Result<Self, dbus::Error> {
+        let id = Rc::new(Cell::new(0));
+        let rc = Rc::clone(&id);
+        conn.add_match(
+            MatchRule::new_signal(INTERFACE, "ActionInvoked"),
+            move |(invoked, action): (u32, String), _: &LocalConnection, _: &Message| {
+                if invoked == rc.get() {
+                    let result = match action.as_str() {
+                        "next" => db.send(Command::Next),
+                        "pause" => db.pause_output().and_then(|_| db.send(Command::Pause)),
+                        _ => Ok(()),
+                    };
+                    if let Err(e) = result {
+                        warn!("unable to run notification action {}: {}", action, e);
+                    }
+                }
+                true
+            },
+        )?;
+
+        Ok(Self {
+            conn,
+            id,
+            track: Cell::new(None),
+        })
+    }
+
+    /// Whether the notification on screen is about the track `id`.
+    pub fn showing(&self, id: usize) -> bool {
+        self.track.get() == Some(id)
+    }
+
+    /// Shows `track`, described by its MPRIS `metadata`, in place of the
+    /// previous notification.
+    pub fn notify(&self, track: &Track, metadata: &PropMap) -> Result<(), dbus::Error> {
+        let summary = prop_cast::<String>(metadata, "xesam:title")
+            .cloned()
+            .unwrap_or_default();
+        let body = match &track.formatted.notification {
+            Some(text) => escape(text),
+            None => default_body(metadata),
+        };
+
+        let mut hints = PropMap::new();
+        if let Some(art) = prop_cast::<String>(metadata, "mpris:artUrl") {
+            hints.insert("image-path".to_string(), Variant(Box::new(art.clone())));
+        }
+        // Track changes are not worth keeping in the server's history
+        hints.insert("transient".to_string(), Variant(Box::new(true)));
+
+        let proxy = self.conn.with_proxy(
+            "org.freedesktop.Notifications",
+            "/org/freedesktop/Notifications",
+            TIMEOUT,
+        );
+        let (id,): (u32,) = proxy.method_call(
+            INTERFACE,
+            "Notify",
+            (
+                "DeaDBeeF",
+                self.id.get(),
+                "deadbeef",
+                summary,
+                body,
+                ACTIONS.to_vec(),
+                hints,
+                -1i32,
+            ),
+        )?;
+
+        self.id.set(id);
+        self.track.set(Some(track.id));
+        Ok(())
+    }
+}
+
+/// The artist and album, one per line.
+fn default_body(metadata: &PropMap) -> String {
+    let artist = prop_cast::<Vec<String>>(metadata, "xesam:artist").map(|a| a.join(", "));
+    let album = prop_cast::<String>(metadata, "xesam:album").cloned();
+
+    [artist, album]
+        .into_iter()
+        .flatten()
+        .filter(|line| !line.is_empty())
+        .map(|line| escape(&line))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Escapes the characters servers read as body markup.
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
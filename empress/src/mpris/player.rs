@@ -1,31 +1,43 @@
-use crate::deadbeef;
+use crate::{
+    art::ArtFinder,
+    backend::{Backend, Track},
+    deadbeef::{Command, Feature, OutputState},
+    trace,
+};
 use dbus::{
-    arg::{Iter, IterAppend, Variant},
-    MethodErr,
+    arg::{Iter, IterAppend, PropMap},
+    MethodErr, Path,
 };
 use dbus_tree::{
     Access, DataType, Factory, Interface, MTFn, MethodInfo, MethodResult, MethodType, PropInfo,
 };
-use std::{collections::HashMap, rc::Rc, sync::Arc};
+use std::{rc::Rc, sync::Arc};
+
+use super::metadata::{can_seek, track_id, track_length, track_metadata};
 
-type MD = HashMap<String, dbus::arg::Variant<Box<dyn dbus::arg::RefArg>>>;
+/// Error for requests the player cannot carry out.
+fn not_supported(msg: &str) -> MethodErr {
+    ("org.freedesktop.DBus.Error.NotSupported", msg).into()
+}
 
 pub(super) struct Player {
-    api: &'static deadbeef::DB_functions_t,
+    db: Rc<dyn Backend>,
+    art: Rc<ArtFinder>,
 }
 
 /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html
 impl Player {
     pub(super) fn from_factory<M, D>(
         f: &Factory<MTFn>,
-        api: &'static deadbeef::DB_functions_t,
+        db: Rc<dyn Backend>,
+        art: Rc<ArtFinder>,
     ) -> Arc<Interface<M, D>>
     where
         D: DataType,
         M: MethodType<D>,
         std::sync::Arc<dbus_tree::Interface<M, D>>: From<dbus_tree::Interface<MTFn, ()>>,
     {
-        let s = Rc::new(Self { api });
+        let s = Rc::new(Self { db, art });
 
         let mut interface = f.interface("org.mpris.MediaPlayer2.Player", ());
 
@@ -48,18 +60,28 @@ impl Player {
         interface = interface.add_m(f.method("Play", (), move |m| rc.play(m)));
 
         let rc = Rc::clone(&s);
-        interface = interface.add_m(f.method("Seek", (), move |m| rc.seek(m)));
+        interface = interface.add_m(
+            f.method("Seek", (), move |m| rc.seek(m))
+                .inarg::<i64, _>("Offset"),
+        );
 
         let rc = Rc::clone(&s);
-        interface = interface.add_m(f.method("SetPosition", (), move |m| rc.set_position(m)));
+        interface = interface.add_m(
+            f.method("SetPosition", (), move |m| rc.set_position(m))
+                .inarg::<Path, _>("TrackId")
+                .inarg::<i64, _>("Position"),
+        );
 
         let rc = Rc::clone(&s);
-        interface = interface.add_m(f.method("OpenUri", (), move |m| rc.open_uri(m)));
+        interface = interface.add_m(
+            f.method("OpenUri", (), move |m| rc.open_uri(m))
+                .inarg::<String, _>("Uri"),
+        );
 
         let rc = Rc::clone(&s);
         interface = interface.add_s({
             rc.seeked();
-            f.signal("Seeked", ())
+            f.signal("Seeked", ()).sarg::<i64, _>("Position")
         });
 
         let rc = Rc::clone(&s);
@@ -78,18 +100,21 @@ impl Player {
                 .on_set(move |i, m| set_rc.set_playback_rate(i, m)),
         );
 
-        let rc = Rc::clone(&s);
-        let set_rc = Rc::clone(&s);
-        interface = interface.add_p(
-            f.property::<bool, _>("Shuffle", ())
-                .access(Access::ReadWrite)
-                .on_get(move |i, m| rc.get_shuffle(i, m))
-                .on_set(move |i, m| set_rc.set_shuffle(i, m)),
-        );
+        // Shuffle is optional, so players without it leave it out
+        if s.db.has(Feature::Shuffle) {
+            let rc = Rc::clone(&s);
+            let set_rc = Rc::clone(&s);
+            interface = interface.add_p(
+                f.property::<bool, _>("Shuffle", ())
+                    .access(Access::ReadWrite)
+                    .on_get(move |i, m| rc.get_shuffle(i, m))
+                    .on_set(move |i, m| set_rc.set_shuffle(i, m)),
+            );
+        }
 
         let rc = Rc::clone(&s);
         interface = interface.add_p(
-            f.property::<MD, _>("Metadata", ())
+            f.property::<PropMap, _>("Metadata", ())
                 .access(Access::Read)
                 .on_get(move |i, m| rc.get_metadata(i, m)),
         );
@@ -175,113 +200,73 @@ impl Player {
     /// Next() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Next
     fn next(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_NEXT, 0, 0, 0);
-        }
+        self.db.send(Command::Next)?;
         Ok(vec![])
     }
 
     /// Previous() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Previous
     fn previous(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_PREV, 0, 0, 0);
-        }
+        self.db.send(Command::Previous)?;
         Ok(vec![])
     }
 
     /// Pause() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Pause
     fn pause(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let output_fn = self
-                .api
-                .get_output
-                .ok_or_else(|| MethodErr::failed("unable to get output"))?;
-
-            let output = output_fn()
-                .as_ref()
-                .ok_or_else(|| MethodErr::failed("null output device returned"))?;
-
-            let pause_fn = output
-                .pause
-                .ok_or_else(|| MethodErr::failed("unable to pause on output"))?;
-
-            pause_fn();
-        }
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_PAUSE, 0, 0, 0);
-        }
+        self.db.pause_output()?;
+        self.db.send(Command::Pause)?;
         Ok(vec![])
     }
 
     /// PlayPause() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:PlayPause
     fn play_pause(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_TOGGLE_PAUSE, 0, 0, 0);
-        }
+        self.db.send(Command::TogglePause)?;
         Ok(vec![])
     }
 
     /// Stop() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Stop
     fn stop(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_STOP, 0, 0, 0);
-        }
+        self.db.send(Command::Stop)?;
         Ok(vec![])
     }
 
     /// Play() -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Play
     fn play(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        unsafe {
-            let sendmessage_fn = self
-                .api
-                .sendmessage
-                .ok_or_else(|| MethodErr::failed("unable to get sendmessage function"))?;
-
-            sendmessage_fn(deadbeef::DB_EV_PLAY_CURRENT, 0, 0, 0);
-        }
+        self.db.send(Command::PlayCurrent)?;
         Ok(vec![])
     }
 
     /// Seek(x: Offset) -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:Seek
-    fn seek(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        Err(MethodErr::failed("CanSeek is permantently false"))
+    fn seek(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let offset: i64 = m.msg.read1()?;
+        let (_, length) = self.seekable()?;
+
+        let position = (self.db.position()?.as_micros() as i64).saturating_add(offset);
+        if position > length {
+            self.db.send(Command::Next)?;
+        } else {
+            self.seek_to(position.max(0))?;
+        }
+        Ok(vec![])
     }
 
     /// SetPosition(o: TrackId, x: Position) -> nothing
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Method:SetPosition
-    fn set_position(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
-        Err(MethodErr::failed("CanSeek is permantently false"))
+    fn set_position(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let (path, position): (Path, i64) = m.msg.read2()?;
+        let (track, length) = self.seekable()?;
+
+        // Requests for a track that is no longer playing are stale, and
+        // positions outside the track are ignored
+        if track_id(&path) == Some(track.id) && (0..=length).contains(&position) {
+            self.seek_to(position)?;
+        }
+        Ok(vec![])
     }
 
     /// OpenUri(s: Uri) -> nothing
@@ -291,12 +276,34 @@ impl Player {
     }
 }
 
+impl Player {
+    /// The playing track and its length in microseconds, or an error if
+    /// nothing seekable is playing.
+    fn seekable(&self) -> Result<(Track, i64), MethodErr> {
+        let track = self
+            .db
+            .playing_track()?
+            .ok_or_else(|| not_supported("no track is playing"))?;
+        match track_length(&track) {
+            Some(length) => Ok((track, length)),
+            None => Err(not_supported("the playing track cannot be seeked")),
+        }
+    }
+
+    /// Seeks the playing track to `position`, in microseconds.
+    fn seek_to(&self, position: i64) -> Result<(), MethodErr> {
+        let ms = u32::try_from(position / 1000).unwrap_or(u32::MAX);
+        self.db.send(Command::Seek(ms))?;
+        Ok(())
+    }
+}
+
 // Signals
 impl Player {
     /// Seeked(x: Position)
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Signal:Seeked
     fn seeked(&self) {
-        println!("Seeked signal");
+        trace!("Seeked signal");
     }
 }
 
@@ -310,38 +317,13 @@ impl Player {
         i: &mut IterAppend,
         _m: &PropInfo<MTFn, ()>,
     ) -> Result<(), MethodErr> {
-        println!("Get playback status called");
-
-        let playback_state = unsafe {
-            let output_fn = self
-                .api
-                .get_output
-                .ok_or_else(|| MethodErr::failed("unable to get output"))?;
-
-            let output = output_fn()
-                .as_ref()
-                .ok_or_else(|| MethodErr::failed("null output device returned"))?;
-
-            let playback_state_fn = output
-                .state
-                .ok_or_else(|| MethodErr::failed("unable to get playback state for output"))?;
-
-            playback_state_fn()
+        let state = match self.db.output_state()? {
+            OutputState::Stopped => "Stopped",
+            OutputState::Playing => "Playing",
+            OutputState::Paused => "Paused",
         };
 
-        let state = match playback_state {
-            deadbeef::DDB_PLAYBACK_STATE_STOPPED => "Stopped",
-            deadbeef::DDB_PLAYBACK_STATE_PLAYING => "Playing",
-            deadbeef::DDB_PLAYBACK_STATE_PAUSED => "Paused",
-            _ => {
-                return Err(MethodErr::failed(&format!(
-                    "invalid playback state: {}",
-                    playback_state
-                )))
-            }
-        };
-
-        println!("Playback status: {}", state);
+        trace!("playback status: {}", state);
 
         i.append(state);
 
@@ -383,8 +365,14 @@ impl Player {
     /// Rate - d
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Rate
     /// Emits changed signal containing new value
-    fn set_playback_rate(&self, _i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        Ok(())
+    fn set_playback_rate(&self, i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+        let rate: f64 = i.read()?;
+        // MinimumRate and MaximumRate are both 1.0
+        if rate == 1.0 {
+            Ok(())
+        } else {
+            Err(not_supported("the playback rate is fixed at 1.0"))
+        }
     }
 
     /// Shuffle - b
@@ -392,13 +380,7 @@ impl Player {
     /// Optional
     /// Emits changed signal containing new value
     fn get_shuffle(&self, i: &mut IterAppend, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        let shuffled = unsafe {
-            let shuffle_fn = self
-                .api
-                .streamer_get_shuffle
-                .ok_or_else(|| MethodErr::failed("could not get shuffled status"))?;
-            shuffle_fn() > 0
-        };
+        let shuffled = self.db.shuffle()?;
         i.append(shuffled);
         Ok(())
     }
@@ -407,7 +389,9 @@ impl Player {
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Shuffle
     /// Optional
     /// Emits changed signal containing new value
-    fn set_shuffle(&self, _i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+    fn set_shuffle(&self, i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+        let shuffle: bool = i.read()?;
+        self.db.set_shuffle(shuffle)?;
         Ok(())
     }
 
@@ -415,70 +399,12 @@ impl Player {
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Metadata
     /// Emits changed signal containing new value
     fn get_metadata(&self, i: &mut IterAppend, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        println!("Metadata called");
-
-        unsafe {
-            let get_track_fn = self
-                .api
-                .streamer_get_playing_track
-                .ok_or_else(|| MethodErr::failed("could not get playing track"))?;
-
-            let track = get_track_fn();
-
-            if !track.is_null() {
-                let _l = Lock::new(self.api);
-
-                let get_meta_fn = self
-                    .api
-                    .pl_get_metadata_head
-                    .ok_or_else(|| MethodErr::failed("could not get metadata head for track"))?;
-
-                let meta = get_meta_fn(track)
-                    .as_ref()
-                    .ok_or_else(|| MethodErr::failed("null metadata returned"))?;
-
-                let key = std::ffi::CStr::from_ptr(meta.key);
-                let val = std::ffi::CStr::from_ptr(meta.key);
-
-                println!("key: {:#?}\tval: {:#?}", key.to_str(), val.to_str());
-            } else {
-                let get_track_fn = self
-                    .api
-                    .streamer_get_current_fileinfo
-                    .ok_or_else(|| MethodErr::failed("could not get file info"))?;
-
-                let track = get_track_fn();
-
-                if track.is_null() {
-                    println!("TRACK IS NULL");
-                    return Ok(());
-                }
-
-                println!("readpos: {}", track.as_ref().unwrap().readpos);
-            }
-        }
-
-        // https://www.freedesktop.org/wiki/Specifications/mpris-spec/metadata
-        let mut d = MD::new();
-
-        d.insert(
-            String::from("mpris:trackid"),
-            Variant(Box::new(String::from(
-                "/org/mpris/MediaPlayer2/DeaDBeeF/tracks/one",
-            ))),
-        );
-
-        d.insert(
-            String::from("mpris:artUrl"),
-            Variant(Box::new(String::from("file://"))),
-        );
-
-        d.insert(
-            String::from("xesam:title"),
-            Variant(Box::new(String::from("track one"))),
-        );
+        let metadata = match self.db.playing_track()? {
+            Some(track) => track_metadata(&track, &self.art),
+            None => PropMap::new(),
+        };
 
-        i.append(d);
+        i.append(metadata);
         Ok(())
     }
 
@@ -486,14 +412,17 @@ impl Player {
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Volume
     /// Emits changed signal containing new value
     fn get_volume(&self, i: &mut IterAppend, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        i.append(1.0f64);
+        i.append(self.db.volume()?);
         Ok(())
     }
 
     /// Volume - d
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Volume
     /// Emits changed signal containing new value
-    fn set_volume(&self, _i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+    fn set_volume(&self, i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+        let volume: f64 = i.read()?;
+        // Values beyond the range are clamped, as the spec asks
+        self.db.set_volume(volume.clamp(0.0, 1.0))?;
         Ok(())
     }
 
@@ -501,8 +430,7 @@ impl Player {
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:Position
     /// Emits changed signal containing new value
     fn get_position(&self, i: &mut IterAppend, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        println!("get position called");
-        i.append(0i64);
+        i.append(self.db.position()?.as_micros() as i64);
         Ok(())
     }
 
@@ -574,7 +502,8 @@ impl Player {
     /// https://specifications.freedesktop.org/mpris-spec/latest/Player_Interface.html#Property:CanSeek
     /// Emits changed signal containing new value
     fn get_can_seek(&self, i: &mut IterAppend, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
-        i.append(false);
+        let track = self.db.playing_track()?;
+        i.append(track.as_ref().map_or(false, can_seek));
         Ok(())
     }
 
@@ -590,22 +519,3 @@ impl Player {
         Ok(())
     }
 }
-
-pub(super) struct Lock {
-    api: &'static deadbeef::DB_functions_t,
-}
-
-impl Lock {
-    pub fn new(api: &'static deadbeef::DB_functions_t) -> Self {
-        unsafe { (api.pl_lock.unwrap())() };
-        Self { api }
-    }
-}
-
-impl Drop for Lock {
-    fn drop(&mut self) {
-        unsafe {
-            (self.api.pl_unlock.unwrap())();
-        }
-    }
-}
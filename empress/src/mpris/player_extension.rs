@@ -0,0 +1,183 @@
+use crate::{
+    backend::{Backend, Extras, ReplayGainMode},
+    deadbeef::Command,
+};
+use dbus::{
+    arg::{Append, Iter, IterAppend, PropMap, Variant},
+    MethodErr,
+};
+use dbus_tree::{
+    Access, DataType, Factory, Interface, MTFn, MethodInfo, MethodResult, MethodType, PropInfo,
+};
+use std::{rc::Rc, sync::Arc};
+
+pub(super) const INTERFACE: &str = "org.deadbeef.Player";
+
+/// DeaDBeeF's playback controls that have no MPRIS equivalent.
+///
+/// Properties are written to DeaDBeeF's config. Their `PropertiesChanged`
+/// signals are sent once DeaDBeeF reports the config change, so edits made
+/// in the player's own GUI are announced too.
+pub(super) struct PlayerExtension {
+    db: Rc<dyn Backend>,
+}
+
+impl PlayerExtension {
+    pub(super) fn from_factory<M, D>(f: &Factory<MTFn>, db: Rc<dyn Backend>) -> Arc<Interface<M, D>>
+    where
+        D: DataType,
+        M: MethodType<D>,
+        std::sync::Arc<dbus_tree::Interface<M, D>>: From<dbus_tree::Interface<MTFn, ()>>,
+    {
+        let s = Rc::new(Self { db });
+
+        let mut interface = f.interface(INTERFACE, ());
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(f.method("PlayNextAlbum", (), move |m| {
+            rc.send(m, Command::PlayNextAlbum)
+        }));
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(f.method("PlayPreviousAlbum", (), move |m| {
+            rc.send(m, Command::PlayPrevAlbum)
+        }));
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(f.method("PlayRandomAlbum", (), move |m| {
+            rc.send(m, Command::PlayRandomAlbum)
+        }));
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(
+            f.method("SetRating", (), move |m| rc.set_rating(m))
+                .inarg::<f64, _>("Rating"),
+        );
+
+        let rc = Rc::clone(&s);
+        let set_rc = Rc::clone(&s);
+        interface = interface.add_p(
+            f.property::<bool, _>("StopAfterCurrent", ())
+                .access(Access::ReadWrite)
+                .auto_emit_on_set(false)
+                .on_get(move |i, _| rc.get(i, |e| e.stop_after_current))
+                .on_set(move |i, _| {
+                    let stop: bool = i.read()?;
+                    set_rc.set(|e| e.stop_after_current = stop)
+                }),
+        );
+
+        let rc = Rc::clone(&s);
+        let set_rc = Rc::clone(&s);
+        interface = interface.add_p(
+            f.property::<bool, _>("StopAfterAlbum", ())
+                .access(Access::ReadWrite)
+                .auto_emit_on_set(false)
+                .on_get(move |i, _| rc.get(i, |e| e.stop_after_album))
+                .on_set(move |i, _| {
+                    let stop: bool = i.read()?;
+                    set_rc.set(|e| e.stop_after_album = stop)
+                }),
+        );
+
+        let rc = Rc::clone(&s);
+        let set_rc = Rc::clone(&s);
+        interface = interface.add_p(
+            f.property::<String, _>("ReplayGainMode", ())
+                .access(Access::ReadWrite)
+                .auto_emit_on_set(false)
+                .on_get(move |i, _| rc.get(i, |e| replaygain_name(e.replaygain).to_string()))
+                .on_set(move |i, m| set_rc.set_replaygain(i, m)),
+        );
+
+        Arc::from(interface)
+    }
+
+    fn send(&self, _m: &MethodInfo<MTFn, ()>, cmd: Command) -> MethodResult {
+        self.db.send(cmd)?;
+        Ok(vec![])
+    }
+
+    /// SetRating(d: Rating) -> nothing: Rates the playing track from 0.0
+    /// to 1.0, saving the rating to its tags
+    fn set_rating(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let rating: f64 = m.msg.read1()?;
+        if !(0.0..=1.0).contains(&rating) {
+            return Err(MethodErr::invalid_arg(&rating));
+        }
+        let track = self
+            .db
+            .playing_track()?
+            .ok_or_else(|| MethodErr::failed("no track is playing"))?;
+        self.db.set_rating(track.id, rating)?;
+        Ok(vec![])
+    }
+
+    fn get<T: Append>(
+        &self,
+        i: &mut IterAppend,
+        value: impl FnOnce(&Extras) -> T,
+    ) -> Result<(), MethodErr> {
+        i.append(value(&self.db.extras()?));
+        Ok(())
+    }
+
+    fn set(&self, change: impl FnOnce(&mut Extras)) -> Result<(), MethodErr> {
+        let mut extras = self.db.extras()?;
+        change(&mut extras);
+        self.db.set_extras(&extras)?;
+        Ok(())
+    }
+
+    /// ReplayGainMode - s: one of `Off`, `Track`, `Album` and
+    /// `PlaybackOrder`
+    fn set_replaygain(&self, i: &mut Iter, _m: &PropInfo<MTFn, ()>) -> Result<(), MethodErr> {
+        let name: &str = i.read()?;
+        let mode = replaygain_mode(name).ok_or_else(|| MethodErr::invalid_arg(&name))?;
+        self.set(|e| e.replaygain = mode)
+    }
+}
+
+fn replaygain_name(mode: ReplayGainMode) -> &'static str {
+    match mode {
+        ReplayGainMode::Off => "Off",
+        ReplayGainMode::Track => "Track",
+        ReplayGainMode::Album => "Album",
+        ReplayGainMode::PlaybackOrder => "PlaybackOrder",
+    }
+}
+
+fn replaygain_mode(name: &str) -> Option<ReplayGainMode> {
+    match name {
+        "Off" => Some(ReplayGainMode::Off),
+        "Track" => Some(ReplayGainMode::Track),
+        "Album" => Some(ReplayGainMode::Album),
+        "PlaybackOrder" => Some(ReplayGainMode::PlaybackOrder),
+        _ => None,
+    }
+}
+
+/// The properties that differ between `old` and `new`, for
+/// `PropertiesChanged`. Every property is included when `old` is unknown.
+pub(super) fn changed_properties(old: Option<&Extras>, new: &Extras) -> PropMap {
+    let mut props = PropMap::new();
+    if old.map_or(true, |o| o.stop_after_current != new.stop_after_current) {
+        props.insert(
+            "StopAfterCurrent".to_string(),
+            Variant(Box::new(new.stop_after_current)),
+        );
+    }
+    if old.map_or(true, |o| o.stop_after_album != new.stop_after_album) {
+        props.insert(
+            "StopAfterAlbum".to_string(),
+            Variant(Box::new(new.stop_after_album)),
+        );
+    }
+    if old.map_or(true, |o| o.replaygain != new.replaygain) {
+        props.insert(
+            "ReplayGainMode".to_string(),
+            Variant(Box::new(replaygain_name(new.replaygain).to_string())),
+        );
+    }
+    props
+}
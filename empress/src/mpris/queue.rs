@@ -0,0 +1,132 @@
+use crate::{art::ArtFinder, backend::Backend, uri::Location};
+use dbus::{arg::PropMap, blocking::LocalConnection, channel::Sender, Message, MethodErr, Path};
+use dbus_tree::{DataType, Factory, Interface, MTFn, MethodInfo, MethodResult, MethodType};
+use std::{rc::Rc, sync::Arc};
+
+use super::metadata::{track_id, track_metadata, track_path};
+
+pub(super) const INTERFACE: &str = "org.deadbeef.Queue";
+
+/// DeaDBeeF's play queue, which MPRIS has no equivalent of.
+///
+/// Tracks are identified by the same object paths as `mpris:trackid`.
+pub(super) struct Queue {
+    db: Rc<dyn Backend>,
+    art: Rc<ArtFinder>,
+}
+
+impl Queue {
+    pub(super) fn from_factory<M, D>(
+        f: &Factory<MTFn>,
+        db: Rc<dyn Backend>,
+        art: Rc<ArtFinder>,
+    ) -> Arc<Interface<M, D>>
+    where
+        D: DataType,
+        M: MethodType<D>,
+        std::sync::Arc<dbus_tree::Interface<M, D>>: From<dbus_tree::Interface<MTFn, ()>>,
+    {
+        let s = Rc::new(Self { db, art });
+
+        let mut interface = f.interface(INTERFACE, ());
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(
+            f.method("List", (), move |m| rc.list(m))
+                .outarg::<Vec<PropMap>, _>("Tracks"),
+        );
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(
+            f.method("Enqueue", (), move |m| rc.enqueue(m))
+                .inarg::<String, _>("Uri")
+                .outarg::<Path, _>("TrackId"),
+        );
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(
+            f.method("EnqueueTrack", (), move |m| rc.enqueue_track(m))
+                .inarg::<Path, _>("TrackId"),
+        );
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(
+            f.method("Remove", (), move |m| rc.remove(m))
+                .inarg::<Path, _>("TrackId"),
+        );
+
+        let rc = Rc::clone(&s);
+        interface = interface.add_m(f.method("Clear", (), move |m| rc.clear(m)));
+
+        interface = interface.add_s(f.signal("Changed", ()).sarg::<Vec<Path>, _>("Tracks"));
+
+        Arc::from(interface)
+    }
+}
+
+// Methods
+impl Queue {
+    /// List() -> aa{sv}: Metadata of every queued track, in play order
+    fn list(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let tracks: Vec<PropMap> = self
+            .db
+            .queue()?
+            .iter()
+            .map(|track| track_metadata(track, &self.art))
+            .collect();
+        Ok(vec![m.msg.method_return().append1(tracks)])
+    }
+
+    /// Enqueue(s: Uri) -> o: Queues the track at `Uri`, which may be a
+    /// `file://` URI or a path, from any playlist
+    fn enqueue(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let uri: &str = m.msg.read1()?;
+        let id = self
+            .db
+            .find_track(&Location::parse(uri))?
+            .ok_or_else(|| MethodErr::failed(&format!("no track at {} in any playlist", uri)))?;
+        self.db.enqueue(id)?;
+
+        Ok(vec![m.msg.method_return().append1(track_path(id))])
+    }
+
+    /// EnqueueTrack(o: TrackId) -> nothing
+    fn enqueue_track(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let id = read_track_id(m)?;
+        self.db.enqueue(id)?;
+        Ok(vec![])
+    }
+
+    /// Remove(o: TrackId) -> nothing
+    fn remove(&self, m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        let id = read_track_id(m)?;
+        self.db.dequeue(id)?;
+        Ok(vec![])
+    }
+
+    /// Clear() -> nothing
+    fn clear(&self, _m: &MethodInfo<MTFn, ()>) -> MethodResult {
+        self.db.clear_queue()?;
+        Ok(vec![])
+    }
+}
+
+fn read_track_id(m: &MethodInfo<MTFn, ()>) -> Result<usize, MethodErr> {
+    let path: Path = m.msg.read1()?;
+    track_id(&path).ok_or_else(|| MethodErr::invalid_arg(&path))
+}
+
+/// Emits `Changed` with the ids of the queued tracks.
+pub(super) fn emit_changed(conn: &LocalConnection, db: &dyn Backend) -> Result<(), String> {
+    let queue = db
+        .queue()
+        .map_err(|e| format!("unable to read the play queue: {}", e))?;
+    let ids: Vec<Path> = queue.iter().map(|track| track_path(track.id)).collect();
+
+    let msg = Message::new_signal("/org/mpris/MediaPlayer2", INTERFACE, "Changed")
+        .map_err(|e| e.to_string())?
+        .append1(ids);
+    conn.send(msg)
+        .map_err(|_| "unable to send queue change".to_string())?;
+    Ok(())
+}
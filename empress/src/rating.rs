@@ -0,0 +1,141 @@
+//! Track ratings kept in tags, such as `FMPS_RATING` from 0.0 to 1.0 or a
+//! `RATING` of 0 to 5 stars, and their conversion to the 0.0 to 1.0 of
+//! `xesam:userRating`.
+use crate::warn;
+
+/// Tags tried for a rating, as used by most players.
+pub const DEFAULT_RATING_TAGS: &str = "FMPS_RATING=1;RATING=5";
+
+/// A tag holding a rating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RatingTag {
+    pub name: String,
+    /// Value of the best rating, 0 being the worst. With a scale of 1 the
+    /// value is a fraction, larger scales are written in whole steps.
+    pub scale: u32,
+}
+
+impl RatingTag {
+    /// Parses `;`-separated `NAME=scale` entries, such as
+    /// [`DEFAULT_RATING_TAGS`], skipping invalid ones.
+    pub fn parse_list(list: &str) -> Vec<Self> {
+        list.split(';')
+            .map(str::trim)
+            .filter(|entry| !entry.is_empty())
+            .filter_map(|entry| {
+                let tag = entry.split_once('=').and_then(|(name, scale)| {
+                    let name = name.trim();
+                    match scale.trim().parse() {
+                        Ok(scale) if scale > 0 && !name.is_empty() => Some(Self {
+                            name: name.to_string(),
+                            scale,
+                        }),
+                        _ => None,
+                    }
+                });
+                if tag.is_none() {
+                    warn!("ignoring rating tag {:?}, expected NAME=scale", entry);
+                }
+                tag
+            })
+            .collect()
+    }
+
+    fn normalise(&self, value: &str) -> Option<f64> {
+        let value: f64 = value.trim().parse().ok()?;
+        if !value.is_finite() || value < 0.0 {
+            return None;
+        }
+        Some((value / self.scale as f64).min(1.0))
+    }
+
+    fn format(&self, rating: f64) -> String {
+        let value = rating * self.scale as f64;
+        if self.scale == 1 {
+            format!("{}", (value * 100.0).round() / 100.0)
+        } else {
+            format!("{}", value.round())
+        }
+    }
+}
+
+/// The rating in the first of `tags` with a valid value in `metadata`.
+pub fn read(tags: &[RatingTag], metadata: &[(String, String)]) -> Option<f64> {
+    tags.iter().find_map(|tag| {
+        metadata
+            .iter()
+            .filter(|(key, _)| key.eq_ignore_ascii_case(&tag.name))
+            .find_map(|(_, value)| tag.normalise(value))
+    })
+}
+
+/// The tag values that store `rating`, from 0.0 to 1.0.
+///
+/// Every one of `tags` already in `metadata` is updated, keeping its key's
+/// case, so other players see the same rating. Otherwise the first tag is
+/// added.
+pub fn tag_values(
+    tags: &[RatingTag],
+    metadata: &[(String, String)],
+    rating: f64,
+) -> Vec<(String, String)> {
+    let present: Vec<_> = tags
+        .iter()
+        .filter_map(|tag| {
+            let (key, _) = metadata
+                .iter()
+                .find(|(key, _)| key.eq_ignore_ascii_case(&tag.name))?;
+            Some((key.clone(), tag.format(rating)))
+        })
+        .collect();
+    if !present.is_empty() {
+        return present;
+    }
+
+    tags.first()
+        .map(|tag| (tag.name.clone(), tag.format(rating)))
+        .into_iter()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(entries: &[(&str, &str)]) -> Vec<(String, String)> {
+        entries
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn ratings_are_normalised() {
+        let tags = RatingTag::parse_list(DEFAULT_RATING_TAGS);
+
+        assert_eq!(read(&tags, &meta(&[("rating", "4")])), Some(0.8));
+        assert_eq!(
+            read(&tags, &meta(&[("RATING", "4"), ("FMPS_RATING", "0.5")])),
+            Some(0.5)
+        );
+        assert_eq!(read(&tags, &meta(&[("RATING", "9")])), Some(1.0));
+        assert_eq!(read(&tags, &meta(&[("FMPS_RATING", "high")])), None);
+        assert_eq!(read(&tags, &meta(&[("title", "4")])), None);
+    }
+
+    #[test]
+    fn ratings_are_written_to_present_tags() {
+        let tags = RatingTag::parse_list("FMPS_RATING=1; RATING=100; bad; X=0");
+        assert_eq!(tags.len(), 2);
+
+        assert_eq!(
+            tag_values(&tags, &meta(&[("rating", "20")]), 0.8),
+            meta(&[("rating", "80")])
+        );
+        assert_eq!(
+            tag_values(&tags, &meta(&[]), 2.0 / 3.0),
+            meta(&[("FMPS_RATING", "0.67")])
+        );
+        assert!(tag_values(&[], &meta(&[]), 0.5).is_empty());
+    }
+}
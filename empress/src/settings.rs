@@ -0,0 +1,196 @@
+//! Plugin settings, stored in the DeaDBeeF config under `ddb_mpris.*`.
+use std::path::PathBuf;
+
+use crate::{
+    deadbeef::Deadbeef,
+    log::Level,
+    rating::{RatingTag, DEFAULT_RATING_TAGS},
+};
+
+/// Cover file names tried in order, relative to the track's directory.
+pub const DEFAULT_COVER_PATTERNS: &[&str] = &[
+    "folder.*",
+    "cover.*",
+    "front.*",
+    "AlbumArt*.jpg",
+    "Scans/front.*",
+    "Scans/cover.*",
+    "Artwork/front.*",
+    "Artwork/cover.*",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Settings {
+    /// Whether the MPRIS service is registered at all
+    pub enabled: bool,
+    /// Suffix for the bus name when another player owns the base name
+    pub instance_suffix: Option<String>,
+    pub art: ArtSettings,
+    pub title_formats: TitleFormats,
+    /// Tags `xesam:userRating` is read from, in order of preference
+    pub rating_tags: Vec<RatingTag>,
+    /// How the computer is kept awake while playing
+    pub inhibit: Inhibit,
+    /// Show a desktop notification when the track changes
+    pub notify: bool,
+    /// Most verbose log messages written
+    pub log_level: Level,
+}
+
+/// Title-format scripts for the displayed tags, such as
+/// `$if(%title%,%title%,%filename%)`. Empty scripts leave the tag as is.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TitleFormats {
+    /// Script for `xesam:title`
+    pub title: String,
+    /// Script for `xesam:artist`
+    pub artist: String,
+    /// Script for `xesam:album`
+    pub album: String,
+    /// Script for the text of track change notifications
+    pub notification: String,
+}
+
+/// The service asked to keep the computer from sleeping during playback.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Inhibit {
+    #[default]
+    Off,
+    /// A sleep and idle lock from systemd-logind, on the system bus
+    Login1,
+    /// The desktop's `org.freedesktop.ScreenSaver`, which also keeps the
+    /// screen on
+    ScreenSaver,
+}
+
+impl Inhibit {
+    const ALL: [Inhibit; 3] = [Inhibit::Off, Inhibit::Login1, Inhibit::ScreenSaver];
+
+    /// Converts the index stored by the config dialog's selector.
+    pub fn from_index(index: i32) -> Option<Self> {
+        Self::ALL.get(usize::try_from(index).ok()?).copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArtSettings {
+    /// Ask DeaDBeeF's artwork plugin for covers
+    pub plugin: bool,
+    /// Glob patterns for cover files, tried in order
+    pub patterns: Vec<String>,
+    /// Parent directories also searched for cover files, for disc folders
+    pub search_depth: u32,
+    /// Extract pictures embedded in the playing file's tags
+    pub embedded: bool,
+    /// Where extracted pictures are cached, defaulting to the XDG cache
+    pub cache_dir: Option<PathBuf>,
+    /// Size the cache is trimmed to after each write
+    pub cache_max_bytes: u64,
+    /// Largest width or height of covers, when they are downscaled
+    pub thumbnail_size: Option<u32>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            instance_suffix: None,
+            art: ArtSettings::default(),
+            title_formats: TitleFormats::default(),
+            rating_tags: RatingTag::parse_list(DEFAULT_RATING_TAGS),
+            inhibit: Inhibit::Off,
+            notify: false,
+            log_level: Level::Off,
+        }
+    }
+}
+
+impl Default for ArtSettings {
+    fn default() -> Self {
+        Self {
+            plugin: true,
+            patterns: DEFAULT_COVER_PATTERNS
+                .iter()
+                .map(|p| p.to_string())
+                .collect(),
+            search_depth: 1,
+            embedded: true,
+            cache_dir: None,
+            cache_max_bytes: 64 * 1024 * 1024,
+            thumbnail_size: None,
+        }
+    }
+}
+
+impl Settings {
+    /// Reads the settings from the DeaDBeeF config, using the defaults for
+    /// any key that is unset.
+    pub fn from_conf(db: &Deadbeef) -> Self {
+        let defaults = Self::default();
+
+        let enabled = db.conf_int("ddb_mpris.checked", defaults.enabled as i32) != 0;
+        let instance_suffix =
+            Some(db.conf_str("ddb_mpris.instance_suffix", "")).filter(|s| !s.trim().is_empty());
+
+        let art = ArtSettings {
+            plugin: db.conf_int("ddb_mpris.art_plugin", defaults.art.plugin as i32) != 0,
+            patterns: db
+                .conf_str("ddb_mpris.art_patterns", &defaults.art.patterns.join(";"))
+                .split(';')
+                .map(str::trim)
+                .filter(|p| !p.is_empty())
+                .map(str::to_string)
+                .collect(),
+            search_depth: db
+                .conf_int(
+                    "ddb_mpris.art_search_depth",
+                    defaults.art.search_depth as i32,
+                )
+                .max(0) as u32,
+            embedded: db.conf_int("ddb_mpris.art_embedded", defaults.art.embedded as i32) != 0,
+            cache_dir: Some(db.conf_str("ddb_mpris.art_cache_dir", ""))
+                .filter(|s| !s.trim().is_empty())
+                .map(PathBuf::from),
+            cache_max_bytes: db
+                .conf_int(
+                    "ddb_mpris.art_cache_size_mb",
+                    (defaults.art.cache_max_bytes / 1024 / 1024) as i32,
+                )
+                .max(0) as u64
+                * 1024
+                * 1024,
+            thumbnail_size: Some(db.conf_int("ddb_mpris.art_thumbnail_size", 512))
+                .filter(|_| db.conf_int("ddb_mpris.art_thumbnail", 0) != 0)
+                .map(|size| size.max(1) as u32),
+        };
+
+        let title_formats = TitleFormats {
+            title: db.conf_str("ddb_mpris.tf_title", ""),
+            artist: db.conf_str("ddb_mpris.tf_artist", ""),
+            album: db.conf_str("ddb_mpris.tf_album", ""),
+            notification: db.conf_str("ddb_mpris.notify_format", ""),
+        };
+
+        let rating_tags =
+            RatingTag::parse_list(&db.conf_str("ddb_mpris.rating_tags", DEFAULT_RATING_TAGS));
+
+        let inhibit =
+            Inhibit::from_index(db.conf_int("ddb_mpris.inhibit", 0)).unwrap_or(defaults.inhibit);
+
+        let notify = db.conf_int("ddb_mpris.notify", defaults.notify as i32) != 0;
+
+        let log_level =
+            Level::from_index(db.conf_int("ddb_mpris.log_level", 0)).unwrap_or(defaults.log_level);
+
+        Self {
+            enabled,
+            instance_suffix,
+            art,
+            title_formats,
+            rating_tags,
+            inhibit,
+            notify,
+            log_level,
+        }
+    }
+}
@@ -0,0 +1,152 @@
+//! Conversion between local paths and `file://` URIs, as described in
+//! RFC 3986 and RFC 8089.
+use std::{
+    ffi::OsString,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+};
+
+/// Location of a track as given in DeaDBeeF's `:URI` property.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Location {
+    /// A file on the local file system
+    File(PathBuf),
+    /// Anything handled by another VFS plugin, such as `http://` streams,
+    /// `cdda://` tracks or files inside `zip://` archives
+    Other(String),
+}
+
+impl Location {
+    /// Interprets a `:URI` value, which is either a plain path or a URI.
+    pub fn parse(uri: &str) -> Self {
+        if uri.starts_with("file://") {
+            if let Some(path) = file_path(uri) {
+                return Self::File(path);
+            }
+        }
+
+        match scheme(uri) {
+            Some(_) => Self::Other(uri.to_string()),
+            None => Self::File(PathBuf::from(uri)),
+        }
+    }
+
+    /// The location as a URI, with local paths percent-encoded.
+    pub fn to_uri(&self) -> String {
+        match self {
+            Self::File(path) => file_uri(path),
+            Self::Other(uri) => uri.clone(),
+        }
+    }
+}
+
+/// Returns the `file://` URI for an absolute `path`.
+///
+/// Every byte outside the RFC 3986 unreserved set, other than the `/`
+/// separators, is percent-encoded. This covers spaces, `#`, `%` and file
+/// names that are not valid UTF-8.
+pub(crate) fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+
+    for &b in path.as_os_str().as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            uri.push(b as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    uri
+}
+
+/// Returns the local path of a `file://` URI, or `None` if it names
+/// another host or is malformed.
+pub(crate) fn file_path(uri: &str) -> Option<PathBuf> {
+    let rest = uri.strip_prefix("file://")?;
+    // The authority is empty or `localhost` for local files
+    let path = rest.strip_prefix("localhost").unwrap_or(rest);
+    if !path.starts_with('/') {
+        return None;
+    }
+
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+
+    Some(PathBuf::from(OsString::from_vec(decoded)))
+}
+
+/// Returns the scheme of `uri`, if it starts with one followed by `://`.
+pub(crate) fn scheme(uri: &str) -> Option<&str> {
+    let (scheme, _) = uri.split_once("://")?;
+    let mut chars = scheme.chars();
+
+    let valid = chars.next()?.is_ascii_alphabetic()
+        && chars.all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c));
+    valid.then_some(scheme)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn paths_are_percent_encoded() {
+        assert_eq!(
+            file_uri(Path::new("/music/AC#DC/100% Hits/01 - Intro.flac")),
+            "file:///music/AC%23DC/100%25%20Hits/01%20-%20Intro.flac"
+        );
+        assert_eq!(
+            file_uri(Path::new("/music/Sigur Rós/ágætis.ogg")),
+            "file:///music/Sigur%20R%C3%B3s/%C3%A1g%C3%A6tis.ogg"
+        );
+
+        let latin1 = OsString::from_vec(b"/music/caf\xe9.mp3".to_vec());
+        assert_eq!(file_uri(Path::new(&latin1)), "file:///music/caf%E9.mp3");
+    }
+
+    #[test]
+    fn file_uris_are_decoded() {
+        let path = Path::new("/music/AC#DC/100% Hits/ágætis \u{1F3B5}.flac");
+        assert_eq!(file_path(&file_uri(path)).as_deref(), Some(path));
+
+        assert_eq!(
+            file_path("file://localhost/music/a%20b.mp3"),
+            Some(PathBuf::from("/music/a b.mp3"))
+        );
+        assert_eq!(file_path("file://server/music/a.mp3"), None);
+        assert_eq!(file_path("file:///music/bad%2"), None);
+    }
+
+    #[test]
+    fn other_schemes_pass_through() {
+        for uri in [
+            "http://radio.example/stream?id=1&q=a b",
+            "cdda://1.cda",
+            "zip:///music/album.zip:01.mp3",
+        ] {
+            assert_eq!(Location::parse(uri), Location::Other(uri.to_string()));
+            assert_eq!(Location::parse(uri).to_uri(), uri);
+        }
+
+        assert_eq!(
+            Location::parse("/music/a b.mp3"),
+            Location::File(PathBuf::from("/music/a b.mp3"))
+        );
+        assert_eq!(
+            Location::parse("file:///music/a%20b.mp3").to_uri(),
+            "file:///music/a%20b.mp3"
+        );
+    }
+}
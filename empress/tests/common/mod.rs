@@ -0,0 +1,207 @@
+//! Runs the MPRIS service against a [`FakeBackend`] on a private bus.
+#![allow(dead_code)]
+
+use std::{
+    cell::RefCell,
+    io::{BufRead, BufReader},
+    process::{Child, Command, Stdio},
+    rc::Rc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use dbus::{
+    arg::{PropMap, RefArg, Variant},
+    blocking::{
+        stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, LocalConnection, Proxy,
+    },
+    channel::Channel,
+    message::SignalArgs,
+};
+use empress::{backend::FakeBackend, deadbeef::DeadbeefEvent, mpris::MPRIS, settings::Settings};
+
+pub const NAME: &str = "org.mpris.MediaPlayer2.DeaDBeeF";
+pub const PATH: &str = "/org/mpris/MediaPlayer2";
+pub const PLAYER: &str = "org.mpris.MediaPlayer2.Player";
+pub const TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A `dbus-daemon` instance, killed on drop.
+pub struct Bus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl Bus {
+    pub fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address"])
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("dbus-daemon must be installed to run these tests");
+
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .unwrap();
+
+        Self {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    pub fn connect(&self) -> LocalConnection {
+        let mut channel = Channel::open_private(&self.address).unwrap();
+        channel.register().unwrap();
+        LocalConnection::from(channel)
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// The MPRIS service, running on its own thread.
+pub struct Service {
+    pub backend: FakeBackend,
+    pub name: String,
+    events: mpsc::Sender<DeadbeefEvent>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    // Dropped last, so the service disconnects before the bus goes away
+    pub bus: Bus,
+}
+
+impl Service {
+    pub fn start() -> Self {
+        Self::start_on(Bus::start(), FakeBackend::default())
+    }
+
+    pub fn start_on(bus: Bus, backend: FakeBackend) -> Self {
+        Self::start_with(bus, backend, Settings::default())
+    }
+
+    pub fn start_with(bus: Bus, backend: FakeBackend, settings: Settings) -> Self {
+        let (events, rx) = mpsc::channel::<DeadbeefEvent>();
+        let (ready_tx, ready_rx) = mpsc::channel();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let conn = bus.address.clone();
+        let fake = backend.clone();
+        let stopped = Arc::clone(&stop);
+
+        let thread = std::thread::spawn(move || {
+            let mut channel = Channel::open_private(&conn).unwrap();
+            channel.register().unwrap();
+
+            let mut mpris = MPRIS::uninit();
+            mpris
+                .init_on(
+                    LocalConnection::from(channel),
+                    NAME,
+                    &settings,
+                    Rc::new(fake),
+                )
+                .unwrap();
+            ready_tx.send(mpris.bus_name().unwrap()).unwrap();
+
+            while !stopped.load(Ordering::SeqCst) {
+                mpris.process(Duration::from_millis(10)).unwrap();
+                while let Ok(event) = rx.try_recv() {
+                    mpris.handle_event(event);
+                }
+            }
+        });
+
+        let name = ready_rx.recv_timeout(TIMEOUT).unwrap();
+
+        Self {
+            backend,
+            name,
+            events,
+            stop,
+            thread: Some(thread),
+            bus,
+        }
+    }
+
+    /// Delivers `event` to the service as if DeaDBeeF had sent it.
+    pub fn send(&self, event: DeadbeefEvent) {
+        self.events.send(event).unwrap();
+    }
+
+    pub fn proxy<'a>(&self, conn: &'a LocalConnection) -> Proxy<'a, &'a LocalConnection> {
+        conn.with_proxy(self.name.clone(), PATH, TIMEOUT)
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Collects `PropertiesChanged` signals received on `conn`.
+pub struct Changes {
+    received: Rc<RefCell<Vec<PropertiesPropertiesChanged>>>,
+}
+
+impl Changes {
+    pub fn watch(conn: &LocalConnection) -> Self {
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let rc = Rc::clone(&received);
+        conn.add_match(
+            PropertiesPropertiesChanged::match_rule(None, None).static_clone(),
+            move |p: PropertiesPropertiesChanged, _: &LocalConnection, _| {
+                rc.borrow_mut().push(p);
+                true
+            },
+        )
+        .unwrap();
+        Self { received }
+    }
+
+    /// Waits for the next signal, or panics after [`TIMEOUT`].
+    pub fn next(&self, conn: &LocalConnection) -> PropertiesPropertiesChanged {
+        let deadline = Instant::now() + TIMEOUT;
+        loop {
+            if !self.received.borrow().is_empty() {
+                return self.received.borrow_mut().remove(0);
+            }
+            assert!(
+                Instant::now() < deadline,
+                "timed out waiting for PropertiesChanged"
+            );
+            conn.process(Duration::from_millis(10)).unwrap();
+        }
+    }
+}
+
+/// Unpacks a dynamically read `a{sv}` value, such as `Metadata` from a
+/// `PropertiesChanged` signal.
+pub fn unpack_dict(value: &dyn RefArg) -> PropMap {
+    let mut map = PropMap::new();
+    let mut iter = match value.as_iter() {
+        Some(i) => i,
+        None => return map,
+    };
+
+    while let (Some(k), Some(v)) = (iter.next(), iter.next()) {
+        if let Some(key) = k.as_str() {
+            // Values are variants, so unwrap them to their contents
+            let inner = v.as_iter().and_then(|mut i| i.next()).unwrap_or(v);
+            map.insert(key.to_string(), Variant(inner.box_clone()));
+        }
+    }
+    map
+}
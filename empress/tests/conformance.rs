@@ -0,0 +1,352 @@
+//! Checks the service against the MPRIS D-Bus Interface Specification v2.2.
+//!
+//! https://specifications.freedesktop.org/mpris-spec/latest/
+mod common;
+
+use std::collections::BTreeSet;
+
+use common::{unpack_dict, Changes, Service, PLAYER};
+use dbus::{
+    arg::{prop_cast, ArgType, PropMap, RefArg},
+    blocking::stdintf::org_freedesktop_dbus::Properties,
+    Path,
+};
+use empress::{
+    backend::Track,
+    deadbeef::{DeadbeefEvent, OutputState},
+};
+
+const ROOT: &str = "org.mpris.MediaPlayer2";
+const NOT_SUPPORTED: &str = "org.freedesktop.DBus.Error.NotSupported";
+
+/// Members of `org.mpris.MediaPlayer2`, as `(required, descriptor)`.
+const ROOT_SPEC: &[(bool, &str)] = &[
+    (true, "method Raise()"),
+    (true, "method Quit()"),
+    (true, "property CanQuit b read"),
+    (false, "property Fullscreen b readwrite"),
+    (false, "property CanSetFullscreen b read"),
+    (true, "property CanRaise b read"),
+    (true, "property HasTrackList b read"),
+    (true, "property Identity s read"),
+    (false, "property DesktopEntry s read"),
+    (true, "property SupportedUriSchemes as read"),
+    (true, "property SupportedMimeTypes as read"),
+];
+
+/// Members of `org.mpris.MediaPlayer2.Player`, as `(required, descriptor)`.
+const PLAYER_SPEC: &[(bool, &str)] = &[
+    (true, "method Next()"),
+    (true, "method Previous()"),
+    (true, "method Pause()"),
+    (true, "method PlayPause()"),
+    (true, "method Stop()"),
+    (true, "method Play()"),
+    (true, "method Seek(in x Offset)"),
+    (true, "method SetPosition(in o TrackId, in x Position)"),
+    (true, "method OpenUri(in s Uri)"),
+    (true, "signal Seeked(x Position)"),
+    (true, "property PlaybackStatus s read"),
+    (false, "property LoopStatus s readwrite"),
+    (true, "property Rate d readwrite"),
+    (false, "property Shuffle b readwrite"),
+    (true, "property Metadata a{sv} read"),
+    (true, "property Volume d readwrite"),
+    (true, "property Position x read"),
+    (true, "property MinimumRate d read"),
+    (true, "property MaximumRate d read"),
+    (true, "property CanGoNext b read"),
+    (true, "property CanGoPrevious b read"),
+    (true, "property CanPlay b read"),
+    (true, "property CanPause b read"),
+    (true, "property CanSeek b read"),
+    (true, "property CanControl b read"),
+];
+
+fn attr<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let start = line.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len = line[start..].find('"')?;
+    Some(&line[start..start + len])
+}
+
+/// Reduces the members of `interface` in introspection `xml` to
+/// descriptors comparable with the spec tables.
+fn members(xml: &str, interface: &str) -> BTreeSet<String> {
+    let mut found = BTreeSet::new();
+    let mut in_interface = false;
+    let mut member: Option<(String, Vec<String>)> = None;
+
+    for line in xml.lines().map(str::trim) {
+        if line.starts_with("<interface ") {
+            in_interface = attr(line, "name") == Some(interface);
+        } else if line.starts_with("</interface>") {
+            in_interface = false;
+        } else if !in_interface {
+            continue;
+        } else if line.starts_with("<method ") || line.starts_with("<signal ") {
+            let kind = if line.starts_with("<method ") {
+                "method"
+            } else {
+                "signal"
+            };
+            let name = format!("{} {}", kind, attr(line, "name").unwrap());
+            if line.ends_with("/>") {
+                found.insert(format!("{}()", name));
+            } else {
+                member = Some((name, Vec::new()));
+            }
+        } else if line.starts_with("<arg ") {
+            let (_, args) = member.as_mut().expect("argument outside a member");
+            let arg = [
+                attr(line, "direction"),
+                attr(line, "type"),
+                attr(line, "name"),
+            ]
+            .iter()
+            .flatten()
+            .copied()
+            .collect::<Vec<_>>()
+            .join(" ");
+            args.push(arg);
+        } else if line.starts_with("</method>") || line.starts_with("</signal>") {
+            let (name, args) = member.take().unwrap();
+            found.insert(format!("{}({})", name, args.join(", ")));
+        } else if line.starts_with("<property ") {
+            found.insert(format!(
+                "property {} {} {}",
+                attr(line, "name").unwrap(),
+                attr(line, "type").unwrap(),
+                attr(line, "access").unwrap()
+            ));
+        }
+    }
+
+    found
+}
+
+fn assert_matches_spec(xml: &str, interface: &str, spec: &[(bool, &str)]) {
+    let found = members(xml, interface);
+    let known: BTreeSet<String> = spec.iter().map(|(_, d)| d.to_string()).collect();
+
+    for (required, descriptor) in spec {
+        if *required {
+            assert!(
+                found.contains(*descriptor),
+                "{} is missing `{}`",
+                interface,
+                descriptor
+            );
+        }
+    }
+    for descriptor in &found {
+        assert!(
+            known.contains(descriptor),
+            "{} has `{}`, which does not match the spec",
+            interface,
+            descriptor
+        );
+    }
+}
+
+fn track() -> Track {
+    Track {
+        id: 42,
+        metadata: vec![
+            ("title".to_string(), "Track One".to_string()),
+            ("artist".to_string(), "Someone".to_string()),
+            ("album artist".to_string(), "Someone Else".to_string()),
+            ("album".to_string(), "Album".to_string()),
+            (":URI".to_string(), "/music/one.flac".to_string()),
+            (":DURATION".to_string(), "1:03:25.5".to_string()),
+        ],
+        ..Default::default()
+    }
+}
+
+/// Asserts the metadata map uses the types from the metadata spec.
+///
+/// https://www.freedesktop.org/wiki/Specifications/mpris-spec/metadata
+fn assert_metadata_types(metadata: &PropMap) {
+    for (key, expected) in [
+        ("mpris:trackid", ArgType::ObjectPath),
+        ("mpris:length", ArgType::Int64),
+        ("xesam:title", ArgType::String),
+        ("xesam:album", ArgType::String),
+        ("xesam:url", ArgType::String),
+        ("xesam:artist", ArgType::Array),
+        ("xesam:albumArtist", ArgType::Array),
+    ] {
+        let value = metadata
+            .get(key)
+            .unwrap_or_else(|| panic!("metadata is missing {}", key));
+        assert_eq!(value.0.arg_type(), expected, "wrong type for {}", key);
+    }
+}
+
+#[test]
+fn introspection_matches_spec() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+
+    let (xml,): (String,) = service
+        .proxy(&conn)
+        .method_call("org.freedesktop.DBus.Introspectable", "Introspect", ())
+        .unwrap();
+
+    assert_matches_spec(&xml, ROOT, ROOT_SPEC);
+    assert_matches_spec(&xml, PLAYER, PLAYER_SPEC);
+}
+
+#[test]
+fn every_method_can_be_called() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for method in ["Raise", "Quit"] {
+        proxy.method_call::<(), _, _, _>(ROOT, method, ()).unwrap();
+    }
+    for method in ["Next", "Previous", "Pause", "PlayPause", "Stop", "Play"] {
+        proxy
+            .method_call::<(), _, _, _>(PLAYER, method, ())
+            .unwrap();
+    }
+
+    // Nothing is playing, so seeking is refused rather than ignored
+    let seek = proxy.method_call::<(), _, _, _>(PLAYER, "Seek", (1_000_000i64,));
+    assert_eq!(seek.unwrap_err().name(), Some(NOT_SUPPORTED));
+    let set_position = proxy.method_call::<(), _, _, _>(
+        PLAYER,
+        "SetPosition",
+        (Path::from("/org/mpris/MediaPlayer2/tracks/1"), 0i64),
+    );
+    assert_eq!(set_position.unwrap_err().name(), Some(NOT_SUPPORTED));
+
+    // Unsupported URIs may raise an error, but must not break the service
+    let _ = proxy.method_call::<(), _, _, _>(PLAYER, "OpenUri", ("file:///music/one.flac",));
+    proxy.method_call::<(), _, _, _>(ROOT, "Raise", ()).unwrap();
+}
+
+#[test]
+fn every_property_can_be_read() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for (interface, spec) in [(ROOT, ROOT_SPEC), (PLAYER, PLAYER_SPEC)] {
+        let all = proxy.get_all(interface).unwrap();
+
+        for (required, descriptor) in spec.iter() {
+            let name = match descriptor.strip_prefix("property ") {
+                Some(d) => d.split(' ').next().unwrap(),
+                None => continue,
+            };
+            assert!(
+                !required || all.contains_key(name),
+                "GetAll({}) is missing {}",
+                interface,
+                name
+            );
+        }
+    }
+}
+
+#[test]
+fn rate_is_fixed_at_normal_speed() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for property in ["Rate", "MinimumRate", "MaximumRate"] {
+        let rate: f64 = proxy.get(PLAYER, property).unwrap();
+        assert_eq!(rate, 1.0, "{}", property);
+    }
+
+    proxy.set(PLAYER, "Rate", 1.0f64).unwrap();
+    let faster = proxy.set(PLAYER, "Rate", 2.0f64);
+    assert_eq!(faster.unwrap_err().name(), Some(NOT_SUPPORTED));
+}
+
+#[test]
+fn metadata_is_empty_without_a_track() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+    assert!(metadata.is_empty());
+}
+
+#[test]
+fn metadata_describes_the_playing_track() {
+    let service = Service::start();
+    service.backend.state().playing = Some(track());
+    let conn = service.bus.connect();
+
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+
+    assert_metadata_types(&metadata);
+    assert_eq!(
+        prop_cast::<Path>(&metadata, "mpris:trackid"),
+        Some(&Path::from("/org/mpris/MediaPlayer2/tracks/42"))
+    );
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"Track One".to_string())
+    );
+    assert_eq!(
+        prop_cast::<i64>(&metadata, "mpris:length"),
+        Some(&3_805_500_000)
+    );
+}
+
+#[test]
+fn song_changed_emits_typed_metadata() {
+    let service = Service::start();
+    service.backend.state().playing = Some(track());
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.send(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+
+    let changed = changes.next(&conn);
+    assert_eq!(changed.interface_name, PLAYER);
+    assert!(changed.invalidated_properties.is_empty());
+
+    let metadata = &changed.changed_properties["Metadata"];
+    assert_eq!(metadata.0.signature().to_string(), "a{sv}");
+    assert_metadata_types(&unpack_dict(&*metadata.0));
+}
+
+#[test]
+fn song_started_emits_playing() {
+    let service = Service::start();
+    service.backend.state().output_state = OutputState::Playing;
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.send(DeadbeefEvent::SongStarted(None));
+
+    let changed = changes.next(&conn);
+    let status = &changed.changed_properties["PlaybackStatus"];
+    assert_eq!(status.0.arg_type(), ArgType::String);
+    assert_eq!(status.0.as_str(), Some("Playing"));
+}
+
+#[test]
+fn paused_emits_playback_status() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    for (paused, expected) in [(true, "Paused"), (false, "Playing")] {
+        service.send(DeadbeefEvent::Paused(paused));
+
+        let changed = changes.next(&conn);
+        let status = &changed.changed_properties["PlaybackStatus"];
+        assert_eq!(status.0.arg_type(), ArgType::String);
+        assert_eq!(status.0.as_str(), Some(expected));
+    }
+}
@@ -0,0 +1,188 @@
+mod common;
+
+use std::{
+    cell::RefCell,
+    io::{ErrorKind, Read},
+    os::unix::{io::IntoRawFd, net::UnixStream},
+    rc::Rc,
+};
+
+use common::{Bus, Changes, Service};
+use dbus::{
+    arg::OwnedFd,
+    blocking::LocalConnection,
+    channel::{MatchingReceiver, Sender},
+    message::MatchRule,
+    Message,
+};
+use empress::{
+    backend::FakeBackend,
+    deadbeef::{DeadbeefEvent, OutputState},
+    settings::{Inhibit, Settings},
+};
+
+/// Calls received by a stand-in `org.freedesktop.ScreenSaver`.
+#[derive(Debug, PartialEq)]
+enum Call {
+    Inhibit,
+    UnInhibit(u32),
+}
+
+/// Serves `org.freedesktop.ScreenSaver` on `conn`, handing out cookie 7.
+fn screen_saver(conn: &LocalConnection) -> Rc<RefCell<Vec<Call>>> {
+    conn.request_name("org.freedesktop.ScreenSaver", false, false, true)
+        .unwrap();
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let rc = Rc::clone(&calls);
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg: Message, conn: &LocalConnection| {
+            let reply = match msg.member().as_deref() {
+                Some("Inhibit") => {
+                    rc.borrow_mut().push(Call::Inhibit);
+                    msg.method_return().append1(7u32)
+                }
+                Some("UnInhibit") => {
+                    rc.borrow_mut().push(Call::UnInhibit(msg.read1().unwrap()));
+                    msg.method_return()
+                }
+                _ => return true,
+            };
+            conn.send(reply).unwrap();
+            true
+        }),
+    );
+    calls
+}
+
+/// Serves `org.freedesktop.login1` on `conn`, keeping the other end of
+/// each lock it hands out.
+fn login1(conn: &LocalConnection) -> Rc<RefCell<Vec<UnixStream>>> {
+    conn.request_name("org.freedesktop.login1", false, false, true)
+        .unwrap();
+    let locks = Rc::new(RefCell::new(Vec::new()));
+    let rc = Rc::clone(&locks);
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg: Message, conn: &LocalConnection| {
+            if msg.member().as_deref() != Some("Inhibit") {
+                return true;
+            }
+            let (what, _, _, mode): (&str, &str, &str, &str) = msg.read4().unwrap();
+            assert_eq!((what, mode), ("sleep:idle", "block"));
+
+            let (ours, theirs) = UnixStream::pair().unwrap();
+            ours.set_nonblocking(true).unwrap();
+            rc.borrow_mut().push(ours);
+            let fd = unsafe { OwnedFd::new(theirs.into_raw_fd()) };
+            conn.send(msg.method_return().append1(fd)).unwrap();
+            true
+        }),
+    );
+    locks
+}
+
+/// Whether the service still holds the lock whose other end is `lock`.
+fn held(mut lock: &UnixStream) -> bool {
+    match lock.read(&mut [0; 1]) {
+        Ok(0) => false,
+        Err(e) if e.kind() == ErrorKind::WouldBlock => true,
+        other => panic!("unexpected read from lock: {:?}", other),
+    }
+}
+
+fn settings(inhibit: Inhibit) -> Settings {
+    Settings {
+        inhibit,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn screen_saver_is_inhibited_while_playing() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let calls = screen_saver(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let service = Service::start_with(bus, FakeBackend::default(), settings(Inhibit::ScreenSaver));
+
+    // Each status change is announced after the lock is updated
+    service.send(DeadbeefEvent::Paused(false));
+    changes.next(&stand_in);
+    service.send(DeadbeefEvent::SongStarted(None));
+    changes.next(&stand_in);
+    assert_eq!(*calls.borrow(), vec![Call::Inhibit]);
+
+    service.send(DeadbeefEvent::Paused(true));
+    changes.next(&stand_in);
+    assert_eq!(*calls.borrow(), vec![Call::Inhibit, Call::UnInhibit(7)]);
+}
+
+#[test]
+fn playing_at_registration_is_inhibited() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let calls = screen_saver(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let backend = FakeBackend::default();
+    backend.state().output_state = OutputState::Playing;
+    let _service = Service::start_with(bus, backend, settings(Inhibit::ScreenSaver));
+
+    // The status is announced once the lock is taken
+    changes.next(&stand_in);
+    assert_eq!(*calls.borrow(), vec![Call::Inhibit]);
+}
+
+#[test]
+fn stopping_releases_the_lock() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let calls = screen_saver(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let service = Service::start_with(bus, FakeBackend::default(), settings(Inhibit::ScreenSaver));
+
+    service.send(DeadbeefEvent::SongStarted(None));
+    changes.next(&stand_in);
+
+    // Nothing is playing after the change
+    service.send(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+    changes.next(&stand_in);
+    assert_eq!(*calls.borrow(), vec![Call::Inhibit, Call::UnInhibit(7)]);
+}
+
+#[test]
+fn nothing_is_inhibited_by_default() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let calls = screen_saver(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let service = Service::start_on(bus, FakeBackend::default());
+
+    service.send(DeadbeefEvent::Paused(false));
+    changes.next(&stand_in);
+    assert!(calls.borrow().is_empty());
+}
+
+#[test]
+fn logind_lock_is_held_while_playing() {
+    let bus = Bus::start();
+    // logind lives on the system bus. No other test here connects to it,
+    // so pointing it at the private bus cannot race.
+    std::env::set_var("DBUS_SYSTEM_BUS_ADDRESS", &bus.address);
+    let stand_in = bus.connect();
+    let locks = login1(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let service = Service::start_with(bus, FakeBackend::default(), settings(Inhibit::Login1));
+
+    service.send(DeadbeefEvent::Paused(false));
+    changes.next(&stand_in);
+    assert_eq!(locks.borrow().len(), 1);
+    assert!(held(&locks.borrow()[0]));
+
+    service.send(DeadbeefEvent::Paused(true));
+    changes.next(&stand_in);
+    assert!(!held(&locks.borrow()[0]));
+}
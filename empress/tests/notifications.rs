@@ -0,0 +1,258 @@
+#![cfg(feature = "notifications")]
+mod common;
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::{Bus, Changes, Service, NAME, TIMEOUT};
+use dbus::{
+    arg::{prop_cast, PropMap},
+    blocking::LocalConnection,
+    channel::{MatchingReceiver, Sender},
+    message::MatchRule,
+    Message,
+};
+use empress::{
+    backend::{FakeBackend, Formatted, Track},
+    deadbeef::{Command, DeadbeefEvent},
+    mpris::MPRIS,
+    settings::Settings,
+};
+
+/// A `Notify` call received by the stand-in server.
+#[derive(Debug, Clone, PartialEq)]
+struct Shown {
+    replaces_id: u32,
+    summary: String,
+    body: String,
+    actions: Vec<String>,
+    image: Option<String>,
+}
+
+/// Serves `org.freedesktop.Notifications` on `conn`, numbering
+/// notifications from 1.
+fn notification_server(conn: &LocalConnection) -> Rc<RefCell<Vec<Shown>>> {
+    conn.request_name("org.freedesktop.Notifications", false, false, true)
+        .unwrap();
+    let shown = Rc::new(RefCell::new(Vec::new()));
+    let rc = Rc::clone(&shown);
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg: Message, conn: &LocalConnection| {
+            if msg.member().as_deref() != Some("Notify") {
+                return true;
+            }
+            let mut args = msg.iter_init();
+            let _app_name: String = args.read().unwrap();
+            let replaces_id: u32 = args.read().unwrap();
+            let _icon: String = args.read().unwrap();
+            let summary: String = args.read().unwrap();
+            let body: String = args.read().unwrap();
+            let actions: Vec<String> = args.read().unwrap();
+            let hints: PropMap = args.read().unwrap();
+
+            rc.borrow_mut().push(Shown {
+                replaces_id,
+                summary,
+                body,
+                actions,
+                image: prop_cast::<String>(&hints, "image-path").cloned(),
+            });
+            let id = rc.borrow().len() as u32;
+            conn.send(msg.method_return().append1(id)).unwrap();
+            true
+        }),
+    );
+    shown
+}
+
+/// Processes `conn` until `count` notifications were shown, or panics
+/// after [`TIMEOUT`].
+fn wait_for(conn: &LocalConnection, shown: &RefCell<Vec<Shown>>, count: usize) {
+    let deadline = Instant::now() + TIMEOUT;
+    while shown.borrow().len() < count {
+        assert!(Instant::now() < deadline, "timed out waiting for Notify");
+        conn.process(Duration::from_millis(10)).unwrap();
+    }
+}
+
+fn track(id: usize, title: &str) -> Track {
+    Track {
+        id,
+        metadata: vec![
+            ("title".to_string(), title.to_string()),
+            ("artist".to_string(), "Someone".to_string()),
+            ("album".to_string(), "Songs & Things".to_string()),
+            (":URI".to_string(), format!("/music/{}.flac", id)),
+        ],
+        ..Default::default()
+    }
+}
+
+fn start(bus: Bus) -> Service {
+    let settings = Settings {
+        notify: true,
+        ..Default::default()
+    };
+    Service::start_with(bus, FakeBackend::default(), settings)
+}
+
+fn song_changed(service: &Service, track: Track) {
+    service.backend.state().playing = Some(track);
+    service.send(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+}
+
+#[test]
+fn song_changes_replace_the_notification() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let shown = notification_server(&stand_in);
+    let service = start(bus);
+
+    song_changed(&service, track(1, "One"));
+    wait_for(&stand_in, &shown, 1);
+    song_changed(&service, track(2, "Two"));
+    wait_for(&stand_in, &shown, 2);
+
+    let shown = shown.borrow();
+    assert_eq!(
+        shown[0],
+        Shown {
+            replaces_id: 0,
+            summary: "One".to_string(),
+            body: "Someone\nSongs &amp; Things".to_string(),
+            actions: ["next", "Next", "pause", "Pause"]
+                .map(String::from)
+                .to_vec(),
+            image: None,
+        }
+    );
+    assert_eq!(shown[1].replaces_id, 1);
+    assert_eq!(shown[1].summary, "Two");
+}
+
+#[test]
+fn song_changes_do_not_wait_for_the_server() {
+    let bus = Bus::start();
+    // Owns the name but never answers
+    let stand_in = bus.connect();
+    stand_in
+        .request_name("org.freedesktop.Notifications", false, false, true)
+        .unwrap();
+    let backend = FakeBackend::default();
+    backend.state().playing = Some(track(1, "One"));
+    let settings = Settings {
+        notify: true,
+        ..Default::default()
+    };
+    let mut mpris = MPRIS::uninit();
+    mpris
+        .init_on(bus.connect(), NAME, &settings, Rc::new(backend))
+        .unwrap();
+
+    // DeaDBeeF's thread only queues the event for the listener
+    let start = Instant::now();
+    mpris.handle_event(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+    assert!(start.elapsed() < Duration::from_millis(100));
+}
+
+#[test]
+fn body_follows_the_title_format() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let shown = notification_server(&stand_in);
+    let service = start(bus);
+
+    song_changed(
+        &service,
+        Track {
+            formatted: Formatted {
+                notification: Some("Someone <live>".to_string()),
+                ..Default::default()
+            },
+            ..track(1, "One")
+        },
+    );
+    wait_for(&stand_in, &shown, 1);
+    assert_eq!(shown.borrow()[0].body, "Someone &lt;live&gt;");
+}
+
+#[cfg(feature = "art")]
+#[test]
+fn album_art_updates_the_notification() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let shown = notification_server(&stand_in);
+    let service = start(bus);
+    service
+        .backend
+        .state()
+        .covers
+        .insert(1, "/covers/one.jpg".into());
+
+    song_changed(&service, track(1, "One"));
+    wait_for(&stand_in, &shown, 2);
+
+    let shown = shown.borrow();
+    assert_eq!(shown[0].image, None);
+    assert_eq!(shown[1].replaces_id, 1);
+    assert_eq!(shown[1].image.as_deref(), Some("file:///covers/one.jpg"));
+}
+
+#[test]
+fn actions_control_the_player() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let shown = notification_server(&stand_in);
+    let service = start(bus);
+
+    song_changed(&service, track(1, "One"));
+    wait_for(&stand_in, &shown, 1);
+
+    for (id, action) in [(9u32, "next"), (1, "next"), (1, "pause")] {
+        let signal = Message::new_signal(
+            "/org/freedesktop/Notifications",
+            "org.freedesktop.Notifications",
+            "ActionInvoked",
+        )
+        .unwrap()
+        .append2(id, action);
+        stand_in.send(signal).unwrap();
+    }
+
+    let deadline = Instant::now() + TIMEOUT;
+    while service.backend.state().commands.len() < 2 {
+        assert!(Instant::now() < deadline, "timed out waiting for actions");
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    // Actions on other programs' notifications are ignored
+    assert_eq!(
+        service.backend.state().commands,
+        vec![Command::Next, Command::Pause]
+    );
+}
+
+#[test]
+fn notifications_are_off_by_default() {
+    let bus = Bus::start();
+    let stand_in = bus.connect();
+    let shown = notification_server(&stand_in);
+    let changes = Changes::watch(&stand_in);
+    let service = Service::start_on(bus, FakeBackend::default());
+
+    song_changed(&service, track(1, "One"));
+    changes.next(&stand_in);
+    // Notify would have been sent before this later change
+    service.send(DeadbeefEvent::Paused(true));
+    changes.next(&stand_in);
+    assert!(shown.borrow().is_empty());
+}
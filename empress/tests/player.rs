@@ -0,0 +1,550 @@
+mod common;
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::{unpack_dict, Bus, Changes, Service, PLAYER, TIMEOUT};
+use dbus::{
+    arg::{prop_cast, PropMap},
+    blocking::{stdintf::org_freedesktop_dbus::Properties, LocalConnection},
+    message::MatchRule,
+    Path,
+};
+use empress::{
+    backend::{FakeBackend, FakeState, Track},
+    deadbeef::{Command, DeadbeefEvent, Error, Feature, OutputState},
+};
+
+fn track() -> Track {
+    Track {
+        id: 7,
+        metadata: vec![
+            ("title".to_string(), "Track One".to_string()),
+            ("artist".to_string(), "Someone".to_string()),
+            (":URI".to_string(), "/music/one.flac".to_string()),
+        ],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn methods_send_commands() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for method in ["Next", "Previous", "PlayPause", "Stop", "Play"] {
+        proxy
+            .method_call::<(), _, _, _>(PLAYER, method, ())
+            .unwrap();
+    }
+
+    assert_eq!(
+        service.backend.state().commands,
+        vec![
+            Command::Next,
+            Command::Previous,
+            Command::TogglePause,
+            Command::Stop,
+            Command::PlayCurrent,
+        ]
+    );
+}
+
+#[test]
+fn pause_pauses_output() {
+    let service = Service::start();
+    service.backend.state().output_state = OutputState::Playing;
+    let conn = service.bus.connect();
+
+    service
+        .proxy(&conn)
+        .method_call::<(), _, _, _>(PLAYER, "Pause", ())
+        .unwrap();
+
+    let state = service.backend.state();
+    assert_eq!(state.output_state, OutputState::Paused);
+    assert_eq!(state.commands, vec![Command::Pause]);
+}
+
+#[test]
+fn backend_errors_are_returned() {
+    let service = Service::start();
+    service.backend.state().error = Some(Error::Missing("sendmessage"));
+    let conn = service.bus.connect();
+
+    let err = service
+        .proxy(&conn)
+        .method_call::<(), _, _, _>(PLAYER, "Next", ())
+        .unwrap_err();
+
+    assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.Failed"));
+}
+
+#[test]
+fn playback_status_follows_backend() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for (state, expected) in [
+        (OutputState::Stopped, "Stopped"),
+        (OutputState::Playing, "Playing"),
+        (OutputState::Paused, "Paused"),
+    ] {
+        service.backend.state().output_state = state;
+        let status: String = proxy.get(PLAYER, "PlaybackStatus").unwrap();
+        assert_eq!(status, expected);
+    }
+}
+
+#[test]
+fn shuffle_follows_backend() {
+    let service = Service::start();
+    service.backend.state().shuffle = true;
+    let conn = service.bus.connect();
+
+    let shuffle: bool = service.proxy(&conn).get(PLAYER, "Shuffle").unwrap();
+    assert!(shuffle);
+}
+
+#[test]
+fn shuffle_can_be_set() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+
+    service.proxy(&conn).set(PLAYER, "Shuffle", true).unwrap();
+    assert!(service.backend.state().shuffle);
+}
+
+#[test]
+fn shuffle_is_left_out_when_unsupported() {
+    let backend = FakeBackend::new(FakeState {
+        missing: vec![Feature::Shuffle],
+        ..Default::default()
+    });
+    let service = Service::start_on(Bus::start(), backend);
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let properties = proxy.get_all(PLAYER).unwrap();
+    assert!(properties.contains_key("PlaybackStatus"));
+    assert!(!properties.contains_key("Shuffle"));
+    assert!(proxy.set(PLAYER, "Shuffle", true).is_err());
+}
+
+#[test]
+fn volume_is_clamped_to_full() {
+    let service = Service::start();
+    service.backend.state().volume = 0.5;
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let volume: f64 = proxy.get(PLAYER, "Volume").unwrap();
+    assert_eq!(volume, 0.5);
+
+    proxy.set(PLAYER, "Volume", 0.25f64).unwrap();
+    assert_eq!(service.backend.state().volume, 0.25);
+    proxy.set(PLAYER, "Volume", 1.5f64).unwrap();
+    assert_eq!(service.backend.state().volume, 1.0);
+    proxy.set(PLAYER, "Volume", -1.0f64).unwrap();
+    assert_eq!(service.backend.state().volume, 0.0);
+}
+
+#[test]
+fn volume_changes_are_announced() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.backend.state().volume = 0.3;
+    service.send(DeadbeefEvent::VolumeChanged);
+
+    let changed = changes.next(&conn);
+    assert_eq!(
+        prop_cast::<f64>(&changed.changed_properties, "Volume"),
+        Some(&0.3)
+    );
+}
+
+/// A three minute track, 10 seconds in.
+fn seekable(service: &Service) {
+    let mut state = service.backend.state();
+    let mut track = track();
+    track
+        .metadata
+        .push((":DURATION".to_string(), "3:00".to_string()));
+    state.playing = Some(track);
+    state.position = Duration::from_secs(10);
+}
+
+#[test]
+fn seek_moves_relative_to_the_position() {
+    let service = Service::start();
+    seekable(&service);
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let position: i64 = proxy.get(PLAYER, "Position").unwrap();
+    assert_eq!(position, 10_000_000);
+
+    for offset in [5_000_000i64, -60_000_000, 600_000_000] {
+        proxy
+            .method_call::<(), _, _, _>(PLAYER, "Seek", (offset,))
+            .unwrap();
+    }
+
+    // Seeking before the start goes to the start, past the end to the
+    // next track
+    assert_eq!(
+        service.backend.state().commands,
+        vec![Command::Seek(15_000), Command::Seek(0), Command::Next]
+    );
+}
+
+#[test]
+fn set_position_ignores_stale_requests() {
+    let service = Service::start();
+    seekable(&service);
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for (track, position) in [(7, 60_000_000i64), (8, 0), (7, -1), (7, 200_000_000)] {
+        let path = Path::from(format!("/org/mpris/MediaPlayer2/tracks/{}", track));
+        proxy
+            .method_call::<(), _, _, _>(PLAYER, "SetPosition", (path, position))
+            .unwrap();
+    }
+
+    assert_eq!(
+        service.backend.state().commands,
+        vec![Command::Seek(60_000)]
+    );
+}
+
+#[test]
+fn streams_cannot_be_seeked() {
+    let service = Service::start();
+    service.backend.state().playing = Some(stream(None));
+    let conn = service.bus.connect();
+
+    let err = service
+        .proxy(&conn)
+        .method_call::<(), _, _, _>(PLAYER, "Seek", (1_000_000i64,))
+        .unwrap_err();
+
+    assert_eq!(err.name(), Some("org.freedesktop.DBus.Error.NotSupported"));
+    assert!(service.backend.state().commands.is_empty());
+}
+
+#[test]
+fn can_seek_follows_the_playing_track() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+    let proxy = service.proxy(&conn);
+
+    let can_seek: bool = proxy.get(PLAYER, "CanSeek").unwrap();
+    assert!(!can_seek);
+    seekable(&service);
+    let can_seek: bool = proxy.get(PLAYER, "CanSeek").unwrap();
+    assert!(can_seek);
+
+    // Announced with each new track
+    let song_changed = |can_seek: bool| {
+        service.send(DeadbeefEvent::SongChanged {
+            from: None,
+            to: None,
+        });
+        let changed = changes.next(&conn);
+        assert_eq!(
+            prop_cast::<bool>(&changed.changed_properties, "CanSeek"),
+            Some(&can_seek)
+        );
+    };
+    service.backend.state().playing = Some(stream(None));
+    song_changed(false);
+    seekable(&service);
+    song_changed(true);
+}
+
+#[test]
+fn seeked_event_emits_seeked() {
+    let service = Service::start();
+    seekable(&service);
+    let conn = service.bus.connect();
+    let seeked = Rc::new(RefCell::new(Vec::new()));
+    let rc = Rc::clone(&seeked);
+    conn.add_match(
+        MatchRule::new_signal(PLAYER, "Seeked"),
+        move |(position,): (i64,), _: &LocalConnection, _| {
+            rc.borrow_mut().push(position);
+            true
+        },
+    )
+    .unwrap();
+
+    service.send(DeadbeefEvent::Seeked {
+        track: None,
+        position: 42.5,
+    });
+
+    let deadline = Instant::now() + TIMEOUT;
+    while seeked.borrow().is_empty() {
+        assert!(Instant::now() < deadline, "timed out waiting for Seeked");
+        conn.process(Duration::from_millis(10)).unwrap();
+    }
+    assert_eq!(*seeked.borrow(), [42_500_000]);
+}
+
+#[test]
+fn paused_event_emits_playback_status() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.send(DeadbeefEvent::Paused(true));
+    let changed = changes.next(&conn);
+    assert_eq!(changed.interface_name, PLAYER);
+    assert_eq!(
+        prop_cast::<String>(&changed.changed_properties, "PlaybackStatus"),
+        Some(&"Paused".to_string())
+    );
+
+    service.send(DeadbeefEvent::Paused(false));
+    let changed = changes.next(&conn);
+    assert_eq!(
+        prop_cast::<String>(&changed.changed_properties, "PlaybackStatus"),
+        Some(&"Playing".to_string())
+    );
+}
+
+#[test]
+fn song_changed_emits_metadata() {
+    let service = Service::start();
+    service.backend.state().playing = Some(track());
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.send(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+
+    let changed = changes.next(&conn);
+    let metadata: PropMap = unpack_dict(&*changed.changed_properties["Metadata"].0);
+
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"Track One".to_string())
+    );
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:url"),
+        Some(&"file:///music/one.flac".to_string())
+    );
+}
+
+#[cfg(feature = "art")]
+#[test]
+fn artwork_plugin_cover_updates_metadata() {
+    let service = Service::start();
+    {
+        let mut state = service.backend.state();
+        state.playing = Some(track());
+        state.covers.insert(7, "/covers/one.jpg".into());
+    }
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.send(DeadbeefEvent::SongChanged {
+        from: None,
+        to: None,
+    });
+
+    let first = changes.next(&conn);
+    let metadata: PropMap = unpack_dict(&*first.changed_properties["Metadata"].0);
+    assert_eq!(prop_cast::<String>(&metadata, "mpris:artUrl"), None);
+
+    let updated = changes.next(&conn);
+    let metadata: PropMap = unpack_dict(&*updated.changed_properties["Metadata"].0);
+    assert_eq!(
+        prop_cast::<String>(&metadata, "mpris:artUrl"),
+        Some(&"file:///covers/one.jpg".to_string())
+    );
+
+    let proxy = service.proxy(&conn);
+    let metadata: PropMap = proxy.get(PLAYER, "Metadata").unwrap();
+    assert_eq!(
+        prop_cast::<String>(&metadata, "mpris:artUrl"),
+        Some(&"file:///covers/one.jpg".to_string())
+    );
+}
+
+#[test]
+fn rating_is_user_rating() {
+    let service = Service::start();
+    service.backend.state().playing = Some(Track {
+        rating: Some(0.6),
+        ..track()
+    });
+    let conn = service.bus.connect();
+
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+    assert_eq!(prop_cast::<f64>(&metadata, "xesam:userRating"), Some(&0.6));
+
+    service.backend.state().playing = Some(track());
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+    assert_eq!(prop_cast::<f64>(&metadata, "xesam:userRating"), None);
+}
+
+#[test]
+fn urls_are_percent_encoded() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for (uri, url) in [
+        (
+            "/music/AC#DC/100% Hits.flac",
+            "file:///music/AC%23DC/100%25%20Hits.flac",
+        ),
+        (
+            "http://radio.example/stream.mp3",
+            "http://radio.example/stream.mp3",
+        ),
+    ] {
+        service.backend.state().playing = Some(Track {
+            id: 1,
+            metadata: vec![(":URI".to_string(), uri.to_string())],
+            ..Default::default()
+        });
+
+        let metadata: PropMap = proxy.get(PLAYER, "Metadata").unwrap();
+        assert_eq!(
+            prop_cast::<String>(&metadata, "xesam:url"),
+            Some(&url.to_string())
+        );
+    }
+}
+
+fn stream(song: Option<(&str, &str)>) -> Track {
+    let mut metadata = vec![
+        (
+            ":URI".to_string(),
+            "http://radio.example/stream.mp3".to_string(),
+        ),
+        ("title".to_string(), "Example FM".to_string()),
+        ("album".to_string(), "Example FM".to_string()),
+        ("genre".to_string(), "Jazz".to_string()),
+        (":BITRATE".to_string(), "128".to_string()),
+        (":DURATION".to_string(), "0:00".to_string()),
+    ];
+    if let Some((artist, title)) = song {
+        metadata.push(("!artist".to_string(), artist.to_string()));
+        metadata.push(("!title".to_string(), title.to_string()));
+    }
+    Track {
+        id: 3,
+        metadata,
+        ..Default::default()
+    }
+}
+
+/// Reads a string list, such as `xesam:artist`, from unpacked metadata.
+fn strings(metadata: &PropMap, key: &str) -> Vec<String> {
+    metadata
+        .get(key)
+        .and_then(|v| v.0.as_iter())
+        .map(|i| i.filter_map(|s| s.as_str().map(str::to_string)).collect())
+        .unwrap_or_default()
+}
+
+#[test]
+fn stream_titles_update_metadata() {
+    let service = Service::start();
+    service.backend.state().playing = Some(stream(None));
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    service.backend.state().playing = Some(stream(Some(("Someone", "A Song"))));
+    service.send(DeadbeefEvent::TrackInfoChanged(None));
+
+    let changed = changes.next(&conn);
+    let metadata: PropMap = unpack_dict(&*changed.changed_properties["Metadata"].0);
+
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"A Song".to_string())
+    );
+    assert_eq!(strings(&metadata, "xesam:artist"), ["Someone"]);
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:album"),
+        Some(&"Example FM".to_string())
+    );
+    assert_eq!(strings(&metadata, "xesam:genre"), ["Jazz"]);
+    assert_eq!(prop_cast::<i32>(&metadata, "deadbeef:bitrate"), Some(&128));
+    assert_eq!(prop_cast::<i64>(&metadata, "mpris:length"), None);
+}
+
+#[test]
+fn tag_edits_update_metadata() {
+    let service = Service::start();
+    service.backend.state().playing = Some(track());
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    let mut edited = track();
+    edited.metadata[0].1 = "Track One (Remastered)".to_string();
+    service.backend.state().playing = Some(edited);
+    service.send(DeadbeefEvent::TrackInfoChanged(None));
+
+    let changed = changes.next(&conn);
+    let metadata: PropMap = unpack_dict(&*changed.changed_properties["Metadata"].0);
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"Track One (Remastered)".to_string())
+    );
+}
+
+#[test]
+fn formatted_tags_replace_raw_tags() {
+    let service = Service::start();
+    let mut playing = track();
+    playing.formatted.title = Some("01. Track One".to_string());
+    playing.formatted.album = Some("Album (1999)".to_string());
+    service.backend.state().playing = Some(playing);
+    let conn = service.bus.connect();
+
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"01. Track One".to_string())
+    );
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:album"),
+        Some(&"Album (1999)".to_string())
+    );
+    assert_eq!(strings(&metadata, "xesam:artist"), ["Someone"]);
+}
+
+#[test]
+fn raw_tags_are_used_without_title_formats() {
+    let backend = FakeBackend::new(FakeState {
+        missing: vec![Feature::TitleFormat],
+        ..Default::default()
+    });
+    let service = Service::start_on(Bus::start(), backend);
+    let mut playing = track();
+    playing.formatted.title = Some("01. Track One".to_string());
+    service.backend.state().playing = Some(playing);
+    let conn = service.bus.connect();
+
+    let metadata: PropMap = service.proxy(&conn).get(PLAYER, "Metadata").unwrap();
+    assert_eq!(
+        prop_cast::<String>(&metadata, "xesam:title"),
+        Some(&"Track One".to_string())
+    );
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "player-extension")]
+mod common;
+
+use common::{Changes, Service};
+use dbus::{
+    arg::{prop_cast, RefArg},
+    blocking::stdintf::org_freedesktop_dbus::Properties,
+};
+use empress::{
+    backend::{Extras, ReplayGainMode, Track},
+    deadbeef::{Command, DeadbeefEvent},
+};
+
+const EXTENSION: &str = "org.deadbeef.Player";
+
+#[test]
+fn album_methods_send_commands() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    for method in ["PlayNextAlbum", "PlayPreviousAlbum", "PlayRandomAlbum"] {
+        proxy
+            .method_call::<(), _, _, _>(EXTENSION, method, ())
+            .unwrap();
+    }
+
+    assert_eq!(
+        service.backend.state().commands,
+        vec![
+            Command::PlayNextAlbum,
+            Command::PlayPrevAlbum,
+            Command::PlayRandomAlbum,
+        ]
+    );
+}
+
+#[test]
+fn properties_change_playback_options() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    proxy.set(EXTENSION, "StopAfterCurrent", true).unwrap();
+    proxy.set(EXTENSION, "StopAfterAlbum", true).unwrap();
+    proxy
+        .set(EXTENSION, "ReplayGainMode", "Album".to_string())
+        .unwrap();
+
+    assert_eq!(
+        service.backend.state().extras,
+        Extras {
+            stop_after_current: true,
+            stop_after_album: true,
+            replaygain: ReplayGainMode::Album,
+        }
+    );
+
+    let mode: String = proxy.get(EXTENSION, "ReplayGainMode").unwrap();
+    assert_eq!(mode, "Album");
+
+    let invalid = proxy.set(EXTENSION, "ReplayGainMode", "Loud".to_string());
+    assert!(invalid.is_err());
+    assert_eq!(
+        service.backend.state().extras.replaygain,
+        ReplayGainMode::Album
+    );
+}
+
+#[test]
+fn config_changes_are_announced() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let changes = Changes::watch(&conn);
+
+    // Changed in DeaDBeeF's GUI rather than over D-Bus
+    service.backend.state().extras.stop_after_current = true;
+    service.send(DeadbeefEvent::ConfigChanged);
+
+    let changed = changes.next(&conn);
+    assert_eq!(changed.interface_name, EXTENSION);
+    assert_eq!(changed.changed_properties.len(), 1);
+    let stop = prop_cast::<bool>(&changed.changed_properties, "StopAfterCurrent");
+    assert_eq!(stop, Some(&true));
+
+    // Unrelated config changes are not announced
+    service.send(DeadbeefEvent::ConfigChanged);
+    service.backend.state().extras.replaygain = ReplayGainMode::Track;
+    service.send(DeadbeefEvent::ConfigChanged);
+
+    let changed = changes.next(&conn);
+    let mode = &changed.changed_properties["ReplayGainMode"];
+    assert_eq!(mode.as_str(), Some("Track"));
+}
+
+#[test]
+fn set_rating_rates_the_playing_track() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let idle: Result<(), _> = proxy.method_call(EXTENSION, "SetRating", (0.5,));
+    assert!(idle.is_err());
+
+    service.backend.state().playing = Some(Track {
+        id: 5,
+        ..Default::default()
+    });
+    let () = proxy.method_call(EXTENSION, "SetRating", (0.8,)).unwrap();
+    assert_eq!(
+        service.backend.state().playing.as_ref().unwrap().rating,
+        Some(0.8)
+    );
+
+    let invalid: Result<(), _> = proxy.method_call(EXTENSION, "SetRating", (1.5,));
+    assert!(invalid.is_err());
+    assert_eq!(
+        service.backend.state().playing.as_ref().unwrap().rating,
+        Some(0.8)
+    );
+}
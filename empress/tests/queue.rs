@@ -0,0 +1,130 @@
+#![cfg(feature = "queue")]
+mod common;
+
+use std::{
+    cell::RefCell,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::{Bus, Service, TIMEOUT};
+use dbus::{
+    arg::{prop_cast, PropMap},
+    blocking::LocalConnection,
+    message::MatchRule,
+    Message, Path,
+};
+use empress::{
+    backend::{FakeBackend, FakeState, Track},
+    deadbeef::{DeadbeefEvent, Feature, PlaylistChange},
+};
+
+const QUEUE: &str = "org.deadbeef.Queue";
+
+fn track(id: usize, uri: &str) -> Track {
+    Track {
+        id,
+        metadata: vec![(":URI".to_string(), uri.to_string())],
+        ..Default::default()
+    }
+}
+
+fn track_id(id: usize) -> Path<'static> {
+    Path::new(format!("/org/mpris/MediaPlayer2/tracks/{}", id)).unwrap()
+}
+
+fn queued(service: &Service) -> Vec<usize> {
+    service.backend.state().queue.iter().map(|t| t.id).collect()
+}
+
+#[test]
+fn tracks_are_queued_by_uri_or_id() {
+    let service = Service::start();
+    service.backend.state().library = vec![track(1, "/music/a b.flac"), track(2, "/music/c.flac")];
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let (id,): (Path,) = proxy
+        .method_call(QUEUE, "Enqueue", ("file:///music/a%20b.flac",))
+        .unwrap();
+    assert_eq!(id, track_id(1));
+
+    let () = proxy
+        .method_call(QUEUE, "EnqueueTrack", (track_id(2),))
+        .unwrap();
+    assert_eq!(queued(&service), vec![1, 2]);
+
+    let (tracks,): (Vec<PropMap>,) = proxy.method_call(QUEUE, "List", ()).unwrap();
+    let ids: Vec<_> = tracks
+        .iter()
+        .map(|t| prop_cast::<Path>(t, "mpris:trackid").cloned())
+        .collect();
+    assert_eq!(ids, vec![Some(track_id(1)), Some(track_id(2))]);
+
+    let () = proxy.method_call(QUEUE, "Remove", (track_id(1),)).unwrap();
+    assert_eq!(queued(&service), vec![2]);
+
+    let () = proxy.method_call(QUEUE, "Clear", ()).unwrap();
+    assert_eq!(queued(&service), Vec::<usize>::new());
+}
+
+#[test]
+fn unknown_tracks_are_rejected() {
+    let service = Service::start();
+    service.backend.state().library = vec![track(1, "/music/a.flac")];
+    let conn = service.bus.connect();
+    let proxy = service.proxy(&conn);
+
+    let missing: Result<(Path,), _> = proxy.method_call(QUEUE, "Enqueue", ("/music/b.flac",));
+    assert!(missing.is_err());
+
+    let missing: Result<(), _> = proxy.method_call(QUEUE, "EnqueueTrack", (track_id(9),));
+    assert!(missing.is_err());
+
+    let not_queued: Result<(), _> = proxy.method_call(QUEUE, "Remove", (track_id(1),));
+    assert!(not_queued.is_err());
+
+    let invalid: Result<(), _> = proxy.method_call(QUEUE, "Remove", (Path::from("/other"),));
+    assert!(invalid.is_err());
+    assert!(queued(&service).is_empty());
+}
+
+#[test]
+fn queue_changes_emit_changed() {
+    let service = Service::start();
+    let conn = service.bus.connect();
+
+    let received = Rc::new(RefCell::new(Vec::new()));
+    let rc = Rc::clone(&received);
+    conn.add_match(
+        MatchRule::new_signal(QUEUE, "Changed"),
+        move |(ids,): (Vec<Path<'static>>,), _: &LocalConnection, _: &Message| {
+            rc.borrow_mut().push(ids);
+            true
+        },
+    )
+    .unwrap();
+
+    service.backend.state().queue = vec![track(3, "/music/a.flac"), track(4, "/music/b.flac")];
+    service.send(DeadbeefEvent::PlaylistChanged(PlaylistChange::PlayQueue));
+
+    let deadline = Instant::now() + TIMEOUT;
+    while received.borrow().is_empty() {
+        assert!(Instant::now() < deadline, "timed out waiting for Changed");
+        conn.process(Duration::from_millis(10)).unwrap();
+    }
+    assert_eq!(received.borrow()[0], vec![track_id(3), track_id(4)]);
+}
+
+#[test]
+fn queue_is_not_served_without_the_play_queue() {
+    let backend = FakeBackend::new(FakeState {
+        missing: vec![Feature::PlayQueue],
+        ..Default::default()
+    });
+    let service = Service::start_on(Bus::start(), backend);
+    let conn = service.bus.connect();
+
+    let list: Result<(Vec<PropMap>,), _> = service.proxy(&conn).method_call(QUEUE, "List", ());
+    assert!(list.is_err());
+}
@@ -0,0 +1,186 @@
+mod common;
+
+use std::{
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use common::{Bus, Service, NAME, TIMEOUT};
+use dbus::{blocking::LocalConnection, channel::Sender, Message};
+use empress::{
+    backend::FakeBackend,
+    mpris::MPRIS,
+    rating::RatingTag,
+    settings::{Settings, TitleFormats},
+};
+
+/// Waits until the bus reports whether `NAME` has an owner, or panics
+/// after [`TIMEOUT`].
+fn wait_for_owner(conn: &LocalConnection, owned: bool) {
+    let proxy = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", TIMEOUT);
+    let deadline = Instant::now() + TIMEOUT;
+
+    loop {
+        let (has_owner,): (bool,) = proxy
+            .method_call("org.freedesktop.DBus", "NameHasOwner", (NAME,))
+            .unwrap();
+        if has_owner == owned {
+            return;
+        }
+        assert!(Instant::now() < deadline, "{} owned: {}", NAME, has_owner);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+#[test]
+fn shutdown_releases_the_bus_name() {
+    let bus = Bus::start();
+    let conn = bus.connect();
+    let backend = FakeBackend::default();
+    let mut mpris = MPRIS::uninit();
+
+    mpris
+        .init_on(
+            bus.connect(),
+            NAME,
+            &Settings::default(),
+            Rc::new(backend.clone()),
+        )
+        .unwrap();
+    assert!(mpris.is_running());
+    wait_for_owner(&conn, true);
+
+    mpris.shutdown();
+    assert!(!mpris.is_running());
+    assert_eq!(mpris.bus_name(), None);
+    wait_for_owner(&conn, false);
+
+    mpris
+        .init_on(bus.connect(), NAME, &Settings::default(), Rc::new(backend))
+        .unwrap();
+    assert_eq!(mpris.bus_name().as_deref(), Some(NAME));
+    wait_for_owner(&conn, true);
+}
+
+#[test]
+fn display_settings_are_reconfigured_in_place() {
+    let bus = Bus::start();
+    let backend = FakeBackend::default();
+    let mut mpris = MPRIS::uninit();
+    mpris
+        .init_on(
+            bus.connect(),
+            NAME,
+            &Settings::default(),
+            Rc::new(backend.clone()),
+        )
+        .unwrap();
+
+    let formats = TitleFormats {
+        title: "$if(%title%,%title%,%filename%)".to_string(),
+        ..Default::default()
+    };
+    let settings = Settings {
+        title_formats: formats.clone(),
+        ..Default::default()
+    };
+    assert!(mpris.reconfigure(&settings));
+    assert_eq!(backend.state().title_formats, formats);
+
+    let settings = Settings {
+        rating_tags: RatingTag::parse_list("RATING=100"),
+        ..settings
+    };
+    assert!(mpris.reconfigure(&settings));
+    assert_eq!(backend.state().rating_tags, settings.rating_tags);
+
+    let settings = Settings {
+        instance_suffix: Some("second".to_string()),
+        ..settings
+    };
+    assert!(!mpris.reconfigure(&settings));
+}
+
+/// Registers a second service on the bus of `service`.
+fn second(service: &Service, settings: &Settings) -> MPRIS {
+    let mut mpris = MPRIS::uninit();
+    mpris
+        .init_on(
+            service.bus.connect(),
+            NAME,
+            settings,
+            Rc::new(FakeBackend::default()),
+        )
+        .unwrap();
+    mpris
+}
+
+#[test]
+fn second_instance_uses_the_instance_name() {
+    let service = Service::start();
+    assert_eq!(service.name, NAME);
+
+    let mpris = second(&service, &Settings::default());
+    assert_eq!(
+        mpris.bus_name(),
+        Some(format!("{}.instance{}", NAME, std::process::id()))
+    );
+}
+
+#[test]
+fn second_instance_uses_the_configured_suffix() {
+    let service = Service::start();
+    let settings = Settings {
+        instance_suffix: Some(" second ".to_string()),
+        ..Default::default()
+    };
+
+    let mpris = second(&service, &settings);
+    assert_eq!(mpris.bus_name(), Some(format!("{}.second", NAME)));
+}
+
+#[test]
+fn lost_name_falls_back_to_the_instance_name() {
+    let bus = Bus::start();
+    let conn = bus.connect();
+    let mut mpris = MPRIS::uninit();
+    mpris
+        .init_on(
+            bus.connect(),
+            NAME,
+            &Settings::default(),
+            Rc::new(FakeBackend::default()),
+        )
+        .unwrap();
+    wait_for_owner(&conn, true);
+
+    let proxy = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", TIMEOUT);
+    let (owner,): (String,) = proxy
+        .method_call("org.freedesktop.DBus", "GetNameOwner", (NAME,))
+        .unwrap();
+    // The bus only tells the owner, so the signal is sent straight to it
+    let name_lost = |name: &str| {
+        let mut signal =
+            Message::new_signal("/org/freedesktop/DBus", "org.freedesktop.DBus", "NameLost")
+                .unwrap()
+                .append1(name);
+        signal.set_destination(Some(owner.clone().into()));
+        conn.send(signal).unwrap();
+    };
+
+    // Losing other names changes nothing
+    name_lost("org.example.Other");
+    name_lost(NAME);
+
+    let instance_name = format!("{}.instance{}", NAME, std::process::id());
+    let deadline = Instant::now() + TIMEOUT;
+    while mpris.bus_name().as_deref() != Some(instance_name.as_str()) {
+        assert_eq!(mpris.bus_name().as_deref(), Some(NAME));
+        assert!(
+            Instant::now() < deadline,
+            "still named {:?}",
+            mpris.bus_name()
+        );
+        mpris.process(Duration::from_millis(10)).unwrap();
+    }
+}